@@ -0,0 +1,58 @@
+// Reformatting of long paragraphs, made of random words, by each of the
+// line breaking algorithms:
+//
+//     cargo bench
+
+extern crate criterion;
+extern crate subpar;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use subpar::reflow::{Algorithm, Options};
+
+// A paragraph of `n` words of 1 to 9 letters, the same for every run.
+fn paragraph(n: usize) -> String {
+    let mut seed: u32 = 1;
+    let mut text = String::new();
+    for _ in 0..n {
+        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+        let length = 1 + (seed >> 16) as usize % 9;
+        for k in 0..length {
+            text.push((b'a' + ((seed >> k) % 26) as u8) as char);
+        }
+        text.push(' ');
+    }
+    text.push('\n');
+    text
+}
+
+fn algorithms(c: &mut Criterion) {
+    let mut group = c.benchmark_group("algorithm");
+    for &n in &[1000, 10000] {
+        let input = paragraph(n);
+        for &(name, algorithm) in &[("badness", Algorithm::Badness),
+                                    ("knuth-plass", Algorithm::KnuthPlass),
+                                    ("greedy", Algorithm::Greedy)] {
+            let options = Options { algorithm, ..Options::new(79, false) };
+            group.bench_with_input(BenchmarkId::new(name, n), &input, |b, input| {
+                b.iter(|| subpar::reformat_with(input, &options))
+            });
+        }
+    }
+    group.finish();
+}
+
+// Lines of many words, where the badness is computed in O(n log n).
+fn widths(c: &mut Criterion) {
+    let mut group = c.benchmark_group("width");
+    let input = paragraph(10000);
+    for &width in &[40, 400, 4000] {
+        let options = Options::new(width, false);
+        group.bench_with_input(BenchmarkId::from_parameter(width), &input, |b, input| {
+            b.iter(|| subpar::reformat_with(input, &options))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, algorithms, widths);
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+// Defaults for the command line, read from a TOML file such as
+//
+//     width = 72
+//     last = true
+//     sentence_spaces = 1
+//     align = "justify"
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use toml;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    pub width: Option<usize>,
+    pub last: Option<bool>,
+    pub sentence_spaces: Option<usize>,
+    pub align: Option<String>,
+}
+
+const KEYS: &[&str] = &["width", "last", "sentence_spaces", "align"];
+
+// Where the configuration is looked for, in order: only the first file
+// found is read.
+fn paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(".subparrc")];
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => paths.push(PathBuf::from(dir).join("subpar/config.toml")),
+        _ => {
+            if let Some(home) = env::var_os("HOME") {
+                paths.push(PathBuf::from(home).join(".config/subpar/config.toml"));
+            }
+        }
+    }
+    paths
+}
+
+// Reads the first configuration file found, if any.  The unknown keys are
+// skipped with a warning, but a file which is not valid (or cannot be
+// read) is an error.
+pub fn read() -> Config {
+    for path in paths() {
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => fail(&path, e),
+        };
+        let table: toml::Table = text.parse().unwrap_or_else(|e| fail(&path, e));
+        for key in table.keys().filter(|k| !KEYS.contains(&k.as_str())) {
+            eprintln!("subpar: {}: Unknown key '{}'.", path.display(), key);
+        }
+        return toml::Value::Table(table).try_into().unwrap_or_else(|e| fail(&path, e));
+    }
+    Config::default()
+}
+
+fn fail<E: ::std::fmt::Display>(path: &Path, error: E) -> ! {
+    eprintln!("subpar: {}: {}", path.display(), error);
+    process::exit(1);
+}
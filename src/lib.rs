@@ -0,0 +1,652 @@
+//! Subpar is a filter for paragraph reformatting.
+//!
+//! The whole pipeline is available through `reformat_str`; the individual
+//! steps (tokenization, cost model, line breaking) live in `reflow`.  With
+//! the `wasm` feature (and without the default `cli` one), the `wasm`
+//! module exports `reformat_str` and `to_json` to JavaScript.
+
+extern crate hyphenation;
+extern crate regex;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate termcolor;
+extern crate unicode_normalization;
+extern crate unicode_segmentation;
+extern crate unicode_width;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+
+pub mod reflow;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+use std::borrow::Cow;
+use std::io::{self, BufRead, Write};
+use std::thread;
+use reflow::Options;
+
+/// Reformats every paragraph of `input` so that no line is longer than
+/// `width` characters.  If `last` is set, the last line of each paragraph
+/// is made as long as the others.
+pub fn reformat_str(input: &str, width: usize, last: bool) -> String {
+    reformat_with(input, &Options::new(width, last))
+}
+
+/// Same as `reformat_str`, with every parameter of the line breaking
+/// available through `options`.
+pub fn reformat_with(input: &str, options: &Options) -> String {
+    let mut output = Vec::new();
+    reformat_to(input, options, &mut output).expect("writing to a vector cannot fail");
+    String::from_utf8(output).expect("the output is made of pieces of the input")
+}
+
+/// Same as `reformat_with`, writing the result to `out`.
+pub fn reformat_to<W: Write>(input: &str, options: &Options, out: &mut W) -> io::Result<()> {
+    // A byte order mark is not part of the text (but one in the middle of
+    // it, e.g. after a concatenation, is left alone).
+    let input = input.strip_prefix('\u{feff}').unwrap_or(input);
+    let input = &reflow::normalize(input, options)[..];
+    let options = guess(input, options);
+    let newline = newline(input, &options);
+    let mut blocks = reflow::tokenize(input, &options);
+    if options.renumber {
+        reflow::renumber(&mut blocks, &mut Vec::new());
+    }
+    write_blocks(&blocks, None, &options, newline, true, out)
+}
+
+/// Describes the paragraphs of `input`, as tokenized according to
+/// `options`, in JSON.
+pub fn to_json(input: &str, options: &Options) -> String {
+    let input = input.strip_prefix('\u{feff}').unwrap_or(input);
+    let input = &reflow::normalize(input, options)[..];
+    let options = guess(input, options);
+    let metric = options.metric();
+    let blocks: Vec<_> = reflow::tokenize(input, &options)
+        .iter()
+        .map(|b| b.data(metric))
+        .collect();
+    serde_json::to_string_pretty(&blocks).expect("the blocks are plain data") + "\n"
+}
+
+/// Counts the paragraphs, words and sentences of `input`, as tokenized
+/// according to `options`, as `key: value` lines, with the total badness
+/// of their reformatting.
+pub fn measure(input: &str, options: &Options) -> String {
+    let input = input.strip_prefix('\u{feff}').unwrap_or(input);
+    let input = &reflow::normalize(input, options)[..];
+    let options = guess(input, options);
+    let metric = options.metric();
+    let blocks = reflow::tokenize(input, &options);
+    let (mut paragraphs, mut words, mut sentences) = (0, 0, 0);
+    let mut longest = String::new();
+    let mut badness = 0usize;
+    for block in blocks.iter().filter(|b| !b.words.is_empty()) {
+        paragraphs += 1;
+        badness = badness.saturating_add(reflow::reformat(block, &options).total_badness());
+        // The parts of a word split for the line breaks are put together.
+        let mut word = String::new();
+        for part in &block.words {
+            word.push_str(part.text());
+            match *part {
+                reflow::Word::Syllable(_) | reflow::Word::Dash(_) => continue,
+                reflow::Word::EndOfSentence(_) => sentences += 1,
+                _ => {}
+            }
+            words += 1;
+            if metric.width(&word) > metric.width(&longest) {
+                longest = word.clone();
+            }
+            word.clear();
+        }
+    }
+    let average = if sentences > 0 { words as f64 / sentences as f64 } else { 0.0 };
+    format!("paragraphs: {}\nwords: {}\nsentences: {}\nlongest_word: {}\n\
+             words_per_sentence: {:.2}\nbadness: {}\n",
+            paragraphs,
+            words,
+            sentences,
+            longest,
+            average,
+            badness)
+}
+
+/// Same as `reformat_to`, reading `input` one paragraph at a time, so
+/// that it need not fit in memory.  The output is the same, but for the
+/// line endings and the prefix, which are guessed from the first paragraph
+/// (if not given).
+pub fn reformat_stream<R: BufRead, W: Write>(mut input: R,
+                                             options: &Options,
+                                             out: &mut W)
+                                             -> io::Result<()> {
+    let mut options = Cow::Borrowed(options);
+    let mut newline = "\n";
+    // Lines since the last paragraph written, the blank lines before them
+    // and at their end, and the opening fence of the current Markdown code
+    // block if any.
+    let mut chunk = String::new();
+    let mut gap = 0;
+    let mut blank_lines = 0;
+    let mut fence: Option<String> = None;
+    // Indent of the last block written, width set by the last directive,
+    // and lists under way (for their renumbering).
+    let mut previous: Option<String> = None;
+    let mut width = None;
+    let mut levels = Vec::new();
+    // Number of bytes read, which locates the invalid UTF-8.
+    let mut offset = 0;
+    loop {
+        let mut bytes = Vec::new();
+        let end = input.read_until(b'\n', &mut bytes)? == 0;
+        let start = offset;
+        offset += bytes.len();
+        let mut line = decode(bytes, start, options.lossy)?;
+        if previous.is_none() && chunk.is_empty() {
+            if let Some(rest) = line.strip_prefix('\u{feff}') {
+                line = rest.to_string();
+            }
+        }
+        // The lines without words (e.g. `>` alone) count as blank.
+        let blank = reflow::is_wordless(&line, &options);
+        // The chunk is complete before the first line following blank
+        // lines (which may not be the code of Markdown, nor an indented
+        // line of reStructuredText, though), or at the first blank line if
+        // it is to be written at once.
+        let code = options.markdown &&
+                   (fence.is_some() || line.starts_with("    ") || line.starts_with('\t')) ||
+                   options.rst && line.starts_with(reflow::is_space);
+        let flush = options.flush_on_blank && blank && !chunk.is_empty() && fence.is_none();
+        if end || flush || (!blank && blank_lines > 0 && !code) {
+            if previous.is_none() {
+                options = Cow::Owned(guess(&chunk, &options).into_owned());
+                newline = self::newline(&chunk, &options);
+            }
+            if !chunk.is_empty() || previous.is_none() {
+                let text = reflow::normalize(&chunk, &options);
+                let mut blocks = reflow::tokenize_at(&text, &options, width);
+                width = blocks.last().map_or(width, |b| b.width);
+                if options.renumber {
+                    reflow::renumber(&mut blocks, &mut levels);
+                }
+                if let Some(block) = blocks.first_mut() {
+                    block.blank_lines = gap;
+                    write_blocks(&blocks, previous.as_deref(), &options, newline, end, out)?;
+                    previous = blocks.last().map(|b| b.indent.clone());
+                    if options.flush_on_blank {
+                        out.flush()?;
+                    }
+                }
+            }
+            chunk.clear();
+            gap = blank_lines;
+            blank_lines = 0;
+        }
+        if end {
+            break;
+        }
+        if blank {
+            // Leading blank lines are dropped, as by `tokenize`, and those
+            // following a chunk already written separate it from the next.
+            if !chunk.is_empty() {
+                blank_lines += 1;
+                chunk.push_str(&line);
+            } else if previous.is_some() {
+                gap += 1;
+            }
+            continue;
+        }
+        blank_lines = 0;
+        if options.markdown {
+            match fence {
+                Some(ref f) if reflow::closes(&line, f) => fence = None,
+                Some(_) => {}
+                None => fence = reflow::opening_fence(&line).map(str::to_string),
+            }
+        }
+        chunk.push_str(&line);
+    }
+    Ok(())
+}
+
+// Completes `options` with the prefix of `input` if it is to be guessed.
+fn guess<'a>(input: &str, options: &'a Options) -> Cow<'a, Options> {
+    if options.auto_prefix && options.prefix.is_none() {
+        Cow::Owned(Options { prefix: reflow::detect_prefix(input), ..options.clone() })
+    } else {
+        Cow::Borrowed(options)
+    }
+}
+
+fn newline(input: &str, options: &Options) -> &'static str {
+    if options.crlf.unwrap_or_else(|| uses_crlf(input)) {
+        "\r\n"
+    } else {
+        "\n"
+    }
+}
+
+// Writes `blocks`, the first of which follows a block indented by
+// `previous` if any, and the last of which ends the output if `last` is
+// set.
+fn write_blocks<W: Write>(blocks: &[reflow::Block],
+                          previous: Option<&str>,
+                          options: &Options,
+                          newline: &str,
+                          last: bool,
+                          out: &mut W)
+                          -> io::Result<()> {
+    // The blocks are independent: with several jobs, each one renders a
+    // share of them beforehand.
+    let mut rendered = Vec::new();
+    if options.jobs > 1 {
+        rendered.resize(blocks.len(), Vec::new());
+        let share = blocks.len().div_ceil(options.jobs).max(1);
+        thread::scope(|scope| {
+            for (blocks, rendered) in blocks.chunks(share).zip(rendered.chunks_mut(share)) {
+                scope.spawn(move || {
+                    for (block, out) in blocks.iter().zip(rendered) {
+                        render(block, options, newline, out)
+                            .expect("writing to a vector cannot fail");
+                    }
+                });
+            }
+        });
+    }
+    for (k, block) in blocks.iter().enumerate() {
+        // Blank lines within a comment or a quotation keep its markers.
+        let before = if k > 0 { Some(&blocks[k - 1].indent[..]) } else { previous };
+        match (before, &options.paragraph_separator) {
+            (Some(_), Some(separator)) if block.blank_lines > 0 => {
+                if !separator.is_empty() {
+                    write!(out, "{}{}", separator, newline)?;
+                }
+            }
+            (Some(before), _) => {
+                let mut separator = reflow::common_prefix(before, &block.indent);
+                if !options.keep_trailing {
+                    separator = separator.trim_end_matches(reflow::is_space);
+                }
+                let blank_lines = if options.squeeze_blanks {
+                    block.blank_lines.min(1)
+                } else {
+                    block.blank_lines
+                };
+                for _ in 0..blank_lines {
+                    write!(out, "{}{}", pad(separator, block, options), newline)?;
+                }
+            }
+            (None, _) => {}
+        }
+        if last && k + 1 == blocks.len() {
+            let mut end = Vec::new();
+            match rendered.get(k) {
+                Some(rendered) => end.extend_from_slice(rendered),
+                None => render(block, options, newline, &mut end)?,
+            }
+            let end = String::from_utf8(end).expect("the output is made of pieces of the input");
+            write_end(&end, options, newline, out)?;
+            continue;
+        }
+        match rendered.get(k) {
+            Some(rendered) => out.write_all(rendered)?,
+            None => render(block, options, newline, out)?,
+        }
+    }
+    Ok(())
+}
+
+// Writes `text`, the end of the output, without its blank lines (e.g.
+// those ending a code block) and with a single newline if any.
+fn write_end<W: Write>(text: &str,
+                       options: &Options,
+                       newline: &str,
+                       out: &mut W)
+                       -> io::Result<()> {
+    let mut end = text.len();
+    while text[..end].ends_with(newline) {
+        end -= newline.len();
+        let start = text[..end].rfind('\n').map_or(0, |i| i + 1);
+        if !reflow::is_blank(&text[start..end]) {
+            break;
+        }
+        end = start;
+    }
+    out.write_all(&text.as_bytes()[..end])?;
+    if end > 0 && options.final_newline {
+        out.write_all(newline.as_bytes())?;
+    }
+    Ok(())
+}
+
+fn render<W: Write>(block: &reflow::Block,
+                    options: &Options,
+                    newline: &str,
+                    out: &mut W)
+                    -> io::Result<()> {
+    if block.verbatim.is_empty() {
+        let paragraph = reflow::reformat(block, options);
+        if options.verify {
+            if let Err(e) = reflow::verify_paragraph(&paragraph, &block.words) {
+                eprintln!("subpar: Invalid line breaking: {}.", e);
+            }
+        }
+        paragraph.write_to(out, newline, options.debug, options.color)
+    } else {
+        for line in &block.verbatim {
+            write!(out, "{}{}", pad(line, block, options), newline)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes `bytes`, found at `offset` in the input, replacing the invalid
+/// UTF-8 by U+FFFD if `lossy` is set, or else failing with the position of
+/// the first invalid byte.
+pub fn decode(bytes: Vec<u8>, offset: usize, lossy: bool) -> io::Result<String> {
+    match String::from_utf8(bytes) {
+        Ok(text) => Ok(text),
+        Err(e) if lossy => Ok(String::from_utf8_lossy(e.as_bytes()).into_owned()),
+        Err(e) => {
+            let position = offset + e.utf8_error().valid_up_to();
+            Err(io::Error::new(io::ErrorKind::InvalidData,
+                               format!("Invalid UTF-8 at byte {}.", position)))
+        }
+    }
+}
+
+// Pads `line` of `block` with spaces to the width if asked to.
+fn pad<'a>(line: &'a str, block: &reflow::Block, options: &Options) -> Cow<'a, str> {
+    let width = block.width.unwrap_or(options.width);
+    let length = options.metric().width(line);
+    if options.pad && length < width {
+        Cow::Owned(line.to_string() + &" ".repeat(width - length))
+    } else {
+        Cow::Borrowed(line)
+    }
+}
+
+/// Tells whether most lines of `input` end with CRLF rather than LF.
+pub fn uses_crlf(input: &str) -> bool {
+    let crlf = input.matches("\r\n").count();
+    crlf > input.matches('\n').count() - crlf
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stream(input: &str, options: &Options) -> String {
+        let mut out = Vec::new();
+        reformat_stream(input.as_bytes(), options, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    const INPUTS: &[&str] = &["",
+                              "\n\nfoo bar baz\n",
+                              "a\n\n\n> \nb\n",
+                              "a\n> \n\nb\n",
+                              "> a\n>\n>\n> b\n",
+                              "# a b\n#\n\n\n# c\n",
+                              "one two three four five six seven\n\n\n\neight nine\n",
+                              "text\n\n    code  here\n\n    more  code\n\n\nback to text\n",
+                              "text\n\n```\n\n\nx  y\n```\n\nafter\n",
+                              "Code::\n\n    f(x)   =  y\n\n  more  text\n\n| line  one\n",
+                              "<!-- subpar: width=12 -->\nnarrow text wrapped\n\nstill  narrow \
+                               text okay\n\n<!-- subpar: width=default -->\nwide again\n"];
+
+    // Words which, at the end or at the start of a line, could be read as
+    // a hard break, a quotation, a list item, a term...
+    const MARKERS: &str = "aaaa bbbb C:\\ cccc dddd eeee ffff gggg hhhh iiii, then > this and \
+                           >> that, - a dash - and + plus + and * star * then 1. one 2) two \
+                           and 1999. was it.  Note: a term, key: value, ```code``` and ~~~, \
+                           # hash ## and | pipes | with .. dots .. in it.\n\n\
+                           > quoted text with - dashes - and 3. numbers and > signs > in it.\n\n\
+                           - an item with - a dash, 2. numbers, C:\\ paths and > signs.\n";
+
+    #[test]
+    fn markers_round_trip() {
+        let mut variants = vec![Options::new(15, false); 8];
+        variants[1].align = reflow::Align::Justify;
+        variants[2].quotes = true;
+        variants[3].markdown = true;
+        variants[4].quotes = true;
+        variants[4].markdown = true;
+        variants[5].definitions = true;
+        variants[6].markdown = true;
+        variants[6].prose_wrap = true;
+        variants[7].rst = true;
+        for options in &mut variants {
+            for &algorithm in &[reflow::Algorithm::Badness, reflow::Algorithm::Greedy] {
+                options.algorithm = algorithm;
+                for width in 5..40 {
+                    options.width = width;
+                    options.goal = width;
+                    let output = reformat_with(MARKERS, options);
+                    assert_eq!(reformat_with(&output, options), output, "{:?}", output);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn knuth_plass_round_trip() {
+        // Sentence endings followed by a single space, which the lines
+        // should not end with, among words of many lengths.
+        let input = "dolor do incididunt a a ipsum amet. ipsum elit, a elit, elit, eiusmod \
+                     adipiscing a sit ipsum elit, lorem ut incididunt adipiscing adipiscing do \
+                     a a lorem tempor. elit, amet. tempor. a sit do ipsum ut consectetur lorem \
+                     lorem lorem eiusmod sed lorem ut adipiscing eiusmod sit adipiscing tempor. \
+                     lorem sed sit a elit, elit, sed sit consectetur sit eiusmod sit a elit, \
+                     amet. ut lorem adipiscing incididunt ut sed ut eiusmod ipsum dolor eiusmod \
+                     tempor. incididunt amet. ipsum tempor. consectetur ut tempor. tempor. sed \
+                     ut adipiscing sed incididunt ut eiusmod sit amet. amet. do ut elit, \
+                     incididunt sed adipiscing\n";
+        let mut options = Options::new(8, false);
+        options.algorithm = reflow::Algorithm::KnuthPlass;
+        for hyphenate in [None, Some(reflow::Language::EnglishUS)] {
+            options.hyphenate = hyphenate;
+            options.dehyphenate = hyphenate.is_some();
+            for width in 5..40 {
+                options.width = width;
+                options.goal = width;
+                let output = reformat_with(input, &options);
+                assert_eq!(reformat_with(&output, &options), output, "{:?}", output);
+            }
+        }
+    }
+
+    #[test]
+    fn sentence_lines_round_trip() {
+        // Sentence endings followed by a single space and a lowercase word,
+        // which could as well be abbreviations.
+        let input = "We met at noon. then we walked to the fair in town. and we saw the big \
+                     tent, e.g. the red one. it was late when we came home.  The end of it.\n";
+        let mut options = Options::new(8, false);
+        options.sentence_lines = true;
+        for &algorithm in &[reflow::Algorithm::Badness,
+                            reflow::Algorithm::KnuthPlass,
+                            reflow::Algorithm::Greedy] {
+            options.algorithm = algorithm;
+            for width in 5..40 {
+                options.width = width;
+                options.goal = width;
+                let output = reformat_with(input, &options);
+                assert_eq!(reformat_with(&output, &options), output, "{:?}", output);
+            }
+        }
+    }
+
+    #[test]
+    fn backslash_round_trip() {
+        let input = "aaaa bbbb C:\\ cccc dddd eeee ffff gggg hhhh iiii\n";
+        let mut options = Options::new(15, false);
+        options.align = reflow::Align::Justify;
+        let output = reformat_with(input, &options);
+        assert_eq!(output, "aaaa       bbbb\nC:\\ cccc   dddd\neeee  ffff gggg\nhhhh iiii\n");
+        assert_eq!(reformat_with(&output, &options), output);
+    }
+
+    #[test]
+    fn label_wider_than_a_term() {
+        // A label of 25 columns, over the widest term, is wrapped as any
+        // word rather than glued to the end of the line before it.
+        let input = "Some text goes here and continues\n\
+                     abcdefghijklmnopqrstuvwxy: and the value after it\n";
+        let mut options = Options::new(30, false);
+        options.definitions = true;
+        options.max_term_width = Some(20);
+        let output = reformat_with(input, &options);
+        assert_eq!(output, "Some text goes\nhere and continues\nabcdefghijklmnopqrstuvwxy: and\n\
+                            the value after it\n");
+        assert_eq!(reformat_with(&output, &options), output);
+    }
+
+    #[test]
+    fn nothing_from_no_words() {
+        for input in &["", " ", "\n", "\n\n", " \t\n  \n", "\r\n"] {
+            assert_eq!(reformat_str(input, 79, false), "", "{:?}", input);
+            assert_eq!(stream(input, &Options::new(79, false)), "", "{:?}", input);
+        }
+        assert_eq!(reformat_str("foo\n", 79, false), "foo\n");
+    }
+
+    // Number of newlines at the end of `output`.
+    fn trailing_newlines(output: &str) -> usize {
+        output.len() - output.trim_end_matches('\n').len()
+    }
+
+    #[test]
+    fn one_trailing_newline() {
+        for input in &["foo", "foo\n", "foo\n\n\n", "foo bar\nbaz\n", "foo\n\nbar",
+                       "foo\n\nbar\n", "foo\n\n\nbar\n\n\n\n", "foo\n\nbar\n \n\t\n"] {
+            let output = reformat_str(input, 79, false);
+            assert_eq!(trailing_newlines(&output), 1, "{:?}", input);
+            assert_eq!(trailing_newlines(&stream(input, &Options::new(79, false))), 1);
+        }
+        assert_eq!(reformat_str("foo\n\n\nbar\n\n", 79, false), "foo\n\n\nbar\n");
+    }
+
+    #[test]
+    fn final_byte() {
+        let mut options = Options::new(79, false);
+        for input in &["bar foo", "bar\n\nfoo\n", "foo\n\n\n"] {
+            options.final_newline = true;
+            assert_eq!(reformat_with(input, &options).as_bytes().last(), Some(&b'\n'));
+            assert_eq!(stream(input, &options).as_bytes().last(), Some(&b'\n'));
+            options.final_newline = false;
+            assert_eq!(reformat_with(input, &options).as_bytes().last(), Some(&b'o'));
+            assert_eq!(stream(input, &options).as_bytes().last(), Some(&b'o'));
+        }
+    }
+
+    #[test]
+    fn extreme_widths() {
+        let word = "x".repeat(3000);
+        let input = format!("a few words {} and more words after it\n", word);
+        for &width in &[5, 100000] {
+            for &algorithm in &[reflow::Algorithm::Badness,
+                                reflow::Algorithm::KnuthPlass,
+                                reflow::Algorithm::Greedy] {
+                let mut options = Options::new(width, false);
+                options.algorithm = algorithm;
+                let output = reformat_with(&input, &options);
+                if width == 5 {
+                    assert!(output.lines().any(|line| line == word));
+                } else {
+                    assert_eq!(output, input);
+                }
+                options.align = reflow::Align::Justify;
+                options.last = true;
+                assert_eq!(reformat_with(&input, &options).split_whitespace().count(), 9);
+            }
+        }
+    }
+
+    #[test]
+    fn arabic_trailing_spaces() {
+        // The last word ends with a kasra, a combining mark.
+        let input = "مرحبا بالعالمِ   \n";
+        let output = reformat_str(input, 79, false);
+        assert_eq!(output, format!("{}\n", input.trim_end()));
+        assert!(output.trim_end().ends_with('\u{650}'));
+        let mut options = Options::new(79, false);
+        options.prefix = Some("# ".to_string());
+        assert_eq!(reformat_with(&format!("# {}", input), &options), format!("# {}", output));
+    }
+
+    #[test]
+    fn single_line_of_50k_words() {
+        let input = "Lorem ipsum dolor sit amet. ".repeat(10000);
+        let mut options = Options::new(79, false);
+        let ends = |options: &Options| {
+            let blocks = reflow::tokenize(&input, options);
+            assert_eq!(blocks.len(), 1);
+            assert_eq!(blocks[0].words.len(), 50000);
+            blocks[0].words.iter().filter(|w| matches!(w, reflow::Word::EndOfSentence(_))).count()
+        };
+        // A single space after a period leaves the sentence end ambiguous,
+        // except at the end of the input.
+        assert_eq!(ends(&options), 1);
+        options.single_space_sentences = true;
+        assert_eq!(ends(&options), 10000);
+        let output = reformat_with(&input, &options);
+        assert!(output.lines().all(|line| line.chars().count() <= 79));
+        assert_eq!(output.split_whitespace().count(), 50000);
+    }
+
+    #[test]
+    fn tabs_separate_words() {
+        let input = "foo\tbar\u{b}baz\u{c}qux \t quux";
+        let blocks = reflow::tokenize(input, &Options::new(79, false));
+        let words: Vec<&str> = blocks[0].words.iter().map(reflow::Word::text).collect();
+        assert_eq!(words, ["foo", "bar", "baz", "qux", "quux"]);
+        // Paragraphs with tabs, and the same ones with spaces.
+        let inputs = [("foo\tbar baz\tqux\nquux\t\tcorge grault\n",
+                       "foo bar baz qux\nquux  corge grault\n"),
+                      ("\tfoo bar baz qux\n\tquux corge\tgrault\n\n\tgarply waldo\n",
+                       "        foo bar baz qux\n        quux corge grault\n\n        \
+                        garply waldo\n"),
+                      ("one\ttwo.\t\tThree\tfour five\tsix.\n",
+                       "one two.  Three four five six.\n")];
+        for width in 5..30 {
+            for (tabs, spaces) in &inputs {
+                assert_eq!(reformat_str(tabs, width, false),
+                           reformat_str(spaces, width, false).replace("        ", "\t"),
+                           "{:?}",
+                           tabs);
+            }
+        }
+    }
+
+    #[test]
+    fn write_to_a_vector() {
+        let input = "The quick brown fox jumps over the lazy dog.\n\nSecond paragraph.\n";
+        let expected = "The quick brown\nfox jumps over\nthe lazy dog.\n\nSecond\nparagraph.\n";
+        let options = Options::new(15, false);
+        let mut out = Vec::new();
+        reformat_to(input, &options, &mut out).unwrap();
+        assert_eq!(out, expected.as_bytes());
+        let mut out = Vec::new();
+        reformat_stream(input.as_bytes(), &options, &mut out).unwrap();
+        assert_eq!(out, expected.as_bytes());
+        assert_eq!(reformat_str(input, 15, false), expected);
+    }
+
+    #[test]
+    fn stream_as_batch() {
+        let mut variants = vec![Options::new(20, false); 8];
+        variants[1].quotes = true;
+        variants[2].markdown = true;
+        variants[3].rst = true;
+        variants[4].prefix = Some("# ".to_string());
+        variants[5].only_long = true;
+        variants[6].squeeze_blanks = true;
+        variants[7].sentence_lines = true;
+        for options in &variants {
+            for input in INPUTS {
+                assert_eq!(stream(input, options), reformat_with(input, options), "{:?}", input);
+            }
+        }
+    }
+}
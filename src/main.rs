@@ -1,215 +1,728 @@
-extern crate rustc_serialize;
 extern crate docopt;
+extern crate encoding_rs;
+extern crate regex;
+#[macro_use]
+extern crate serde_derive;
+extern crate similar;
+extern crate subpar;
+extern crate terminal_size;
+extern crate toml;
 
-use std::fmt;
-use std::io::{self, Read};
+mod config;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, IsTerminal, Read, Write};
+use std::path::Path;
+use std::process;
+use std::thread;
 use docopt::Docopt;
+use encoding_rs::Encoding;
+use regex::Regex;
+use similar::TextDiff;
+use subpar::reflow::{Algorithm, Align, Language, Normalization, Options, WidthMode};
+use terminal_size::{terminal_size_of, Width};
 
-const USAGE: &'static str = "
+const USAGE: &str = "
 Subpar is a filter for paragraph reformatting.
 
-Usage: subpar [options]
+Usage: subpar [options] [<file>...]
+
+The files, or else the standard input, are reformatted in order, each one
+starting a new paragraph.
+
+The defaults of --width, --last, --sentence-spaces and of the alignment can
+be set (as width, last, sentence_spaces, and align = \"justify\", \"center\"
+or \"right\") in a TOML file: .subparrc, or else
+$XDG_CONFIG_HOME/subpar/config.toml.
+
+A line <!-- subpar: width=<n> --> in the input sets the width of the
+paragraphs following it, until the next one (width=default restores it).
 
 Options:
   -h, --help            Print this message.
+  -V, --version         Print the version of subpar.
+  -i, --in-place        Overwrite each file with its reformatted contents.
+  -o, --output <path>   Write the result to <path> instead of the standard
+                        output.
+  --diff                Print the changes the reformatting would make as a
+                        unified diff, and exit with 1 if there are any.
+  --check               Exit with 1, writing nothing, if the reformatting would
+                        change the input.
+  --json                Describe the paragraphs and their words in JSON
+                        instead of reformatting them.
+  --measure             Print the numbers of paragraphs, words and sentences,
+                        the longest word, the words per sentence and the
+                        total badness of the lines instead of reformatting.
+  --sentences           Start every sentence on a line of its own, wrapping
+                        the sentences too long.
+  --squeeze             Only normalize the spaces, keeping the line breaks.
+  --only-long           Keep the lines which fit as they are, wrapping each of
+                        the others on its own.
+  --verse               Never join the lines (only break those too long, e.g.
+                        with --hang), nor the stanzas.
+  -S, --stream          Read the standard input one paragraph at a time (the
+                        line endings and the prefix are guessed from the
+                        first one).
+  --flush-on-blank      Stream, writing each paragraph as soon as a blank line
+                        follows it.
   -l, --last            Make the last line as long as the others.
+  -k, --knuth           Break the lines with Knuth and Plass' algorithm.
+  -G, --greedy          Fill each line in turn with as many words as fit.
+  -j, --justify         Stretch every line but the last one of each paragraph
+                        to exactly <width> characters.
+  -E, --graphemes       Count user-perceived characters instead of terminal
+                        columns.
+  --width-mode <mode>   Count the width in columns, graphemes (as --graphemes)
+                        or chars (code points) [default: columns].
+  --normalize <form>    Put the input in the Unicode normalization form nfc or
+                        nfd first, or leave it as is if none [default: none].
+  -a, --ansi            Do not count ANSI escape sequences (e.g. colors).
+  -d, --dehyphen        Join the words hyphenated across two lines, where the
+                        dictionary of --hyphenate splits them.
+  -H, --hyphenate <lang>
+                        Hyphenate the words following the rules of <lang>,
+                        given as a code such as en-us or fr.
+  --french-spacing      Put narrow no-break spaces after « and before ; : ! ?
+                        and », where no line may break.
+  --fix-punct-spacing   Put a space after the commas, semicolons and colons
+                        followed by a word (but in numbers and URLs).
+  --break-dashes        Allow breaking the lines after the em dashes within
+                        words.
+  --keep-together <regex>
+                        Keep the runs of words matching <regex> (e.g. dates
+                        such as \\d+ \\w+ \\d+) on the same line.
+  --break-urls          Hyphenate the URLs and file paths like other words
+                        (by default, they are never split).
+  -s, --sentence-spaces <n>
+                        Put <n> spaces after the end of a sentence
+                        (defaults to 2).
+  -n, --no-sentences    Do not detect the ends of sentences: all the words are
+                        separated by a single space.
+  --single-space-sentences
+                        Also end a sentence at a sentence ending followed by
+                        a single space, if the next word is capitalized.
+  -e, --sentence-ends <chars>
+                        The characters that end a sentence [default: .!?…].
+  --abbrev <file>       Also treat the words of <file>, one per line, as
+                        abbreviations which do not end a sentence.
+  -p, --prefix <str>    Strip <str> from the start of the input lines and write
+                        it in front of every output line.
+  -P, --auto-prefix     Guess the prefix from the first line of the input.
+  -q, --quotes          Handle the '>' markers of quoted emails.
+  -m, --markdown        Leave the code blocks of Markdown as they are.
+  --rst                 Leave the literal blocks, line blocks and explicit
+                        markup (e.g. directives) of reStructuredText as they
+                        are.
+  --prose-wrap          Wrap the Markdown as Prettier does with proseWrap:
+                        as --markdown, also keeping the headings, thematic
+                        breaks, tables and HTML as they are, and filling the
+                        lines greedily (unless --knuth) with one space after
+                        the sentences (unless --sentence-spaces).
+  --wrap-after-colon    As --definitions, but only for the labels (such as
+                        Note:) up to 20 columns wide.
+  --renumber            Number the items of the ordered lists of Markdown in
+                        sequence, from the number of the first one.
+  --definitions         Wrap the lines starting with a term such as key: under
+                        the text after it, each term starting a paragraph.
+  --debug               Mark the width at the end of every line, and by how
+                        much the lines exceeding it overflow (in red on a
+                        terminal).
+  --pad                 Pad every line with spaces to <width> characters.
+  --keep-trailing       Keep the whitespace at the end of the last line of every
+                        paragraph, and after the prefix on blank lines.
+  --verify              Warn on stderr about any line which overflows but could
+                        be broken, or about words lost by the line breaking.
+  --ruler               Print a ruler as long as <width> to stderr first.
+  --para-sep <str>      Separate the paragraphs with a line <str> (or nothing
+                        if empty) instead of their blank lines.
+  --squeeze-blanks      Separate the paragraphs with a single blank line,
+                        however many there are in the input.
+  --no-final-newline    Do not end the output with a newline.
+  --from-encoding <name>
+                        Read the input in the encoding <name> (e.g. latin1 or
+                        windows-1252) rather than UTF-8.
+  --to-encoding <name>  Write the output in the encoding <name>.
+  --lossy               Replace the invalid UTF-8 of the input (e.g. Latin-1)
+                        instead of failing.
+  --crlf                End the lines with CRLF (by default, as in the input).
+  --indent-char <char>  Write the indents with tabs (as many as fit) if <char>
+                        is tab, or with spaces only if it is space.
+  --hang <n>            Indent every line of a paragraph but the first one by
+                        <n> spaces [default: 0].
+  --indent <n>          Indent the first line of every paragraph by <n> spaces
+                        [default: 0].
+  --avoid-rivers        With --justify, keep the gaps of consecutive lines
+                        apart (except with --knuth or --greedy).
+  -c, --center          Center every line within <width> characters.
+  -r, --right           Align every line to the right of <width> characters.
+  --penalty-exp <n>     Raise the distance of each line to <goal> to the power
+                        <n> (except with --knuth) [default: 3].
+  --overflow-penalty <n>
+                        Cost of each character past the width
+                        [default: 1000000].
+  --last-line-slack <n>
+                        Make the last line of a paragraph costlier the shorter
+                        it is than <n> characters (unless --last) [default: 0].
+  --last-reward <n>     Make the last line of a paragraph cost as much as the
+                        others (unless --last), divided by <n> if it is
+                        longer than the share --last-min-frac of the width.
+  --last-min-frac <f>   Share of the width past which --last-reward applies
+                        (e.g. 0.5 for half of it) [default: 0.25].
+  --glue-width <f>      Count each space between the words as <f> columns
+                        (e.g. 0.33, to estimate a proportional font) when
+                        breaking the lines, which may then exceed <width>.
+  --jobs <n>            Reformat the paragraphs with <n> threads, 0 meaning
+                        one per processor [default: 1].
+  --min-last <n>        Move words down to the last line of each paragraph
+                        until it has two words or <n> characters
+                        [default: 0].
+  --no-widows           Move a word down to the last line of each paragraph if
+                        it has only one, unless it would overflow.
+  --lines <n>           Break each paragraph into at most <n> lines, as even as
+                        possible, instead of filling them (into more if
+                        they would not fit within <width>).
+  -t, --tab-width <n>   Put the tab stops every <n> columns [default: 8].
   -w, --width <width>   No line in the output may contain more than <width>
-                        characters (newline excluded) [default: 79].
+                        characters (newline excluded), 0 meaning the width
+                        of the terminal and <n>% that share of it (defaults
+                        to $SUBPAR_WIDTH, or 79).
+  -g, --goal <goal>     Make the lines as close as possible to <goal>
+                        characters, at most <width> (defaults to <width>).
 ";
 
-#[derive(Debug, RustcDecodable)]
+#[derive(Debug, Deserialize)]
 struct Args {
     flag_last: bool,
-    flag_width: usize,
+    flag_knuth: bool,
+    flag_greedy: bool,
+    flag_justify: bool,
+    flag_avoid_rivers: bool,
+    flag_center: bool,
+    flag_hang: usize,
+    flag_indent_char: Option<String>,
+    flag_indent: usize,
+    flag_right: bool,
+    flag_crlf: bool,
+    flag_lossy: bool,
+    flag_from_encoding: Option<String>,
+    flag_to_encoding: Option<String>,
+    flag_no_final_newline: bool,
+    flag_para_sep: Option<String>,
+    flag_squeeze_blanks: bool,
+    flag_keep_trailing: bool,
+    flag_pad: bool,
+    flag_debug: bool,
+    flag_ruler: bool,
+    flag_verify: bool,
+    flag_prefix: Option<String>,
+    flag_auto_prefix: bool,
+    flag_quotes: bool,
+    flag_markdown: bool,
+    flag_prose_wrap: bool,
+    flag_rst: bool,
+    flag_definitions: bool,
+    flag_wrap_after_colon: bool,
+    flag_renumber: bool,
+    flag_graphemes: bool,
+    flag_width_mode: String,
+    flag_normalize: String,
+    flag_ansi: bool,
+    flag_dehyphen: bool,
+    flag_hyphenate: Option<String>,
+    flag_break_dashes: bool,
+    flag_fix_punct_spacing: bool,
+    flag_break_urls: bool,
+    flag_keep_together: Option<String>,
+    flag_french_spacing: bool,
+    flag_sentence_spaces: Option<usize>,
+    flag_no_sentences: bool,
+    flag_single_space_sentences: bool,
+    flag_sentence_ends: String,
+    flag_abbrev: Option<String>,
+    flag_penalty_exp: u32,
+    flag_overflow_penalty: usize,
+    flag_last_line_slack: usize,
+    flag_last_reward: Option<usize>,
+    flag_last_min_frac: f64,
+    flag_glue_width: Option<f64>,
+    flag_jobs: usize,
+    flag_min_last: usize,
+    flag_no_widows: bool,
+    flag_lines: Option<usize>,
+    flag_tab_width: usize,
+    flag_width: Option<String>,
+    flag_goal: Option<usize>,
+    flag_version: bool,
+    flag_in_place: bool,
+    flag_output: Option<String>,
+    flag_stream: bool,
+    flag_flush_on_blank: bool,
+    flag_json: bool,
+    flag_measure: bool,
+    flag_squeeze: bool,
+    flag_sentences: bool,
+    flag_verse: bool,
+    flag_only_long: bool,
+    flag_diff: bool,
+    flag_check: bool,
+    arg_file: Vec<String>,
 }
 
-#[derive(Debug)]
-enum Word<'a> {
-    Normal(&'a str),
-    EndOfSentence(&'a str),
-}
+// Used when the width of the terminal is unknown, or when no width is given
+// by --width, $SUBPAR_WIDTH or the configuration.
+const DEFAULT_WIDTH: usize = 79;
 
-// Returns a vector of paragraphs (vectors of words)
-fn tokenize(input: &str) -> Vec<Vec<Word>> {
-    let endings = ".!?…";
-    let mut text = Vec::new();
-    let mut paragraph = Vec::new();
-    let mut last_word: Option<&str> = None;
-    let mut many_spaces = false;
-    let mut newlines = 0;
-    for line in input.lines() {
-        for word in line.split(' ') {
-            if word.is_empty() {
-                many_spaces = true;
-            } else {
-                if let Some(last_word) = last_word {
-                    if last_word.ends_with(|c| endings.contains(c)) &&
-                       (many_spaces || newlines > 0) {
-                        paragraph.push(Word::EndOfSentence(last_word));
-                    } else {
-                        paragraph.push(Word::Normal(last_word));
-                    }
-                    many_spaces = false;
-                    if newlines > 1 {
-                        text.push(paragraph);
-                        paragraph = Vec::new();
-                    }
-                    newlines = 0;
-                }
-                last_word = Some(word);
-            }
+// Widest label wrapped after by --wrap-after-colon.
+const LABEL_WIDTH: usize = 20;
+
+// Reads the width from $SUBPAR_WIDTH, falling back on `default` (with a
+// warning if the variable is set to anything but a positive number).
+fn default_width(default: usize) -> usize {
+    let value = match env::var("SUBPAR_WIDTH") {
+        Err(env::VarError::NotPresent) => return default,
+        value => value.ok(),
+    };
+    match value.and_then(|v| v.trim().parse().ok()) {
+        Some(width) if width > 0 => width,
+        _ => {
+            eprintln!("subpar: Invalid SUBPAR_WIDTH, using {}.", default);
+            default
         }
-        newlines += 1;
-    }
-    if let Some(last_word) = last_word {
-        paragraph.push(Word::EndOfSentence(last_word));
     }
-    text.push(paragraph);
-    text
 }
 
-// Returns a vector of vectors such that `lengths[i][j]` is the length
-// of a line starting with word `i` and ending with word `i+j`.
-fn line_lengths(line: &[Word]) -> Vec<Vec<usize>> {
-    let mut lengths = Vec::with_capacity(line.len() * line.len());
-    let mut length;
-    for i in 0..line.len() {
-        length = 0usize;
-        let mut tmp = Vec::with_capacity(line.len() - i);
-        for word in line[i..].iter() {
-            match *word {
-                Word::Normal(ref w) => {
-                    length += w.chars().count();
-                    tmp.push(length);
-                    length += 1;
-                }
-                Word::EndOfSentence(w) => {
-                    length += w.chars().count();
-                    tmp.push(length);
-                    length += 2;
-                }
+// Reads the argument of --width: a number of characters, or a percentage
+// of the width of the terminal (rounded down, and of 79 if there is no
+// terminal).
+fn parse_width(width: &str) -> usize {
+    match width.strip_suffix('%') {
+        Some(percent) => match percent.parse::<usize>() {
+            Ok(percent) if percent > 0 && percent <= 100 => {
+                let columns = terminal_width().unwrap_or(DEFAULT_WIDTH);
+                (columns * percent / 100).max(1)
             }
-        }
-        lengths.push(tmp);
+            _ => usage_error(&format!("Invalid percentage of the width '{}'.", width)),
+        },
+        None => width.parse()
+            .unwrap_or_else(|_| usage_error(&format!("Invalid width '{}'.", width))),
     }
-    lengths
 }
 
-fn badness(line_length: usize, width: usize) -> usize {
-    if line_length > width {
-        1_000_000 * (line_length - width)
-    } else {
-        (width - line_length).pow(3)
+fn terminal_width() -> Option<usize> {
+    terminal_size_of(io::stdout()).map(|(Width(w), _)| w as usize)
+}
+
+// Rejects the flags which cannot go together, of which only one would
+// otherwise be obeyed.
+fn validate(args: &Args) {
+    let exclusive: &[&[(&str, bool)]] = &[&[("--justify", args.flag_justify),
+                                            ("--center", args.flag_center),
+                                            ("--right", args.flag_right)],
+                                          &[("--markdown", args.flag_markdown),
+                                            ("--rst", args.flag_rst)],
+                                          &[("--prose-wrap", args.flag_prose_wrap),
+                                            ("--rst", args.flag_rst)],
+                                          &[("--knuth", args.flag_knuth),
+                                            ("--greedy", args.flag_greedy)],
+                                          &[("--check", args.flag_check),
+                                            ("--diff", args.flag_diff),
+                                            ("--in-place", args.flag_in_place),
+                                            ("--json", args.flag_json),
+                                            ("--measure", args.flag_measure)]];
+    for flags in exclusive {
+        let given: Vec<&str> = flags.iter().filter(|f| f.1).map(|f| f.0).collect();
+        if given.len() > 1 {
+            usage_error(&format!("The options {} cannot go together.", given.join(" and ")));
+        }
     }
 }
 
-// Contains a vector of lines, each line being a slice of words.
-struct Paragraph<'a> {
-    paragraph: Vec<&'a [Word<'a>]>,
-    maxwidth: usize,
+fn usage_error(message: &str) -> ! {
+    let error = docopt::Error::Argv(message.to_string());
+    docopt::Error::WithProgramUsage(Box::new(error), USAGE.trim().to_string()).exit()
 }
 
-impl<'a> fmt::Display for Paragraph<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for words in &self.paragraph {
-            let mut line = String::new();
-            for word in words.iter() {
-                match *word {
-                    Word::Normal(ref w) => line.push_str(&format!("{} ", w)),
-                    Word::EndOfSentence(ref w) => line.push_str(&format!("{}  ", w)),
-                }
-            }
-            let line = line.trim_right();
-            try!(writeln!(f, "{}", line));
+// Returns a ruler of `width` columns: a '+' every five and the tens digit
+// every ten, as in ----+----1----+----2.
+fn ruler(width: usize) -> String {
+    (1..=width)
+        .map(|i| match i % 10 {
+            0 => (b'0' + (i / 10 % 10) as u8) as char,
+            5 => '+',
+            _ => '-',
+        })
+        .collect()
+}
+
+// Encodings of the input and of the output if not UTF-8, and whether the
+// invalid UTF-8 of the input is replaced.
+#[derive(Clone, Copy)]
+struct Encodings {
+    from: Option<&'static Encoding>,
+    to: Option<&'static Encoding>,
+    lossy: bool,
+}
+
+impl Encodings {
+    fn decode(self, bytes: Vec<u8>) -> io::Result<String> {
+        match self.from {
+            Some(encoding) => Ok(encoding.decode_without_bom_handling(&bytes).0.into_owned()),
+            None => subpar::decode(bytes, 0, self.lossy),
         }
-        Ok(())
     }
-}
 
-impl<'a> fmt::Debug for Paragraph<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for words in &self.paragraph {
-            let mut line = String::new();
-            for word in words.iter() {
-                match *word {
-                    Word::Normal(ref w) => line.push_str(&format!("{} ", w)),
-                    Word::EndOfSentence(ref w) => line.push_str(&format!("{}  ", w)),
-                }
-            }
-            let line = line.trim_right();
-            try!(write!(f, "{}", line));
-            if self.maxwidth >= line.chars().count() {
-                for _ in 0..self.maxwidth - line.chars().count() {
-                    try!(write!(f, " "));
+    // Transcodes `output`, written in UTF-8.  The characters missing from
+    // the encoding are written as numeric character references.
+    fn encode(self, output: Vec<u8>) -> Vec<u8> {
+        match self.to {
+            Some(encoding) => {
+                let text = String::from_utf8(output).expect("the output is made of the input");
+                let (bytes, _, unmappable) = encoding.encode(&text);
+                if unmappable {
+                    eprintln!("subpar: Some characters are missing from {}.", encoding.name());
                 }
-                try!(write!(f, "|{}", self.maxwidth));
+                bytes.into_owned()
             }
-            try!(writeln!(f, ""));
+            None => output,
         }
-        Ok(())
     }
 }
 
-fn reformat<'a>(text: &'a [Word<'a>], args: &Args) -> Paragraph<'a> {
-    let width = args.flag_width;
-    let last = args.flag_last;
-    let n = text.len();
-
-    // Optimize the length of the lines independently (DP).
-    let mut dp = Vec::with_capacity(text.len() * text.len());
-    let lengths = line_lengths(text);
-    dp.push((0, 0));
-    for i in (0..n).rev() {
-        let mut minbadness = None;
-        for j in 1..n - i + 1 {
-            let length = lengths[i][j - 1];
-            let mut localbad = badness(length, width) + dp[n - j - i].0;
-            if !last && i + j == n {
-                // last line
-                if width / 4 < length && length < width {
-                    localbad /= 100;
+// Returns the encoding named `label`, such as latin1 or windows-1252.
+fn encoding(label: &str) -> &'static Encoding {
+    Encoding::for_label(label.as_bytes())
+        .unwrap_or_else(|| usage_error(&format!("Unknown encoding '{}'.", label)))
+}
+
+// Reads the standard input, or the concatenation of `files` separated by
+// blank lines.  Exits on the first error.
+fn read_input(files: &[String], encodings: Encodings) -> String {
+    let mut input = String::new();
+    if files.is_empty() {
+        let mut bytes = Vec::new();
+        match io::stdin().read_to_end(&mut bytes).and_then(|_| encodings.decode(bytes)) {
+            Ok(text) => input = text,
+            Err(e) => {
+                eprintln!("subpar: <stdin>: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+    for file in files {
+        match fs::read(file).and_then(|bytes| encodings.decode(bytes)) {
+            Ok(text) => {
+                if !input.is_empty() {
+                    if !input.ends_with('\n') {
+                        input.push('\n');
+                    }
+                    input.push('\n');
                 }
+                input.push_str(&text);
             }
-            match minbadness {
-                None => minbadness = Some((localbad, j)),
-                Some((m, _)) if localbad < m => minbadness = Some((localbad, j)),
-                _ => {}
+            Err(e) => {
+                eprintln!("subpar: {}: {}", file, e);
+                process::exit(1);
             }
         }
-        dp.push(minbadness.unwrap());
     }
+    input
+}
 
-    // Exploit the DP result to split the lines and produce a paragraph.
-    let mut paragraph = Vec::with_capacity(dp.len() - 1);
-    let mut nb = 0;
-    let mut i = 0;
-    dp.reverse();
-    dp.pop();
-    for (_, k) in dp.into_iter() {
-        if nb == 0 {
-            paragraph.push(&text[i..i + k]);
-            i += k;
-            nb = k;
-        }
-        nb -= 1;
+// Reads each file, or else the standard input, with its name.
+fn inputs(files: &[String], encodings: Encodings) -> Vec<(String, String)> {
+    if files.is_empty() {
+        vec![("<stdin>".to_string(), read_input(files, encodings))]
+    } else {
+        files.iter().map(|f| (f.clone(), read_input(std::slice::from_ref(f), encodings))).collect()
     }
-    Paragraph {
-        paragraph: paragraph,
-        maxwidth: width,
+}
+
+// Replaces the contents of `file` by their reformatting.  The result is
+// written next to it first, so that the file is left untouched if anything
+// goes wrong.
+fn edit_in_place(file: &str, options: &Options, encodings: Encodings) -> io::Result<()> {
+    let path = Path::new(file);
+    let input = encodings.decode(fs::read(path)?)?;
+    let mut output = Vec::new();
+    subpar::reformat_to(&input, options, &mut output)?;
+    let output = encodings.encode(output);
+    let name = path.file_name().map_or("".into(), |n| n.to_string_lossy());
+    let temporary = path.with_file_name(format!(".{}.subpar", name));
+    let written = fs::write(&temporary, output)
+        .and_then(|_| fs::set_permissions(&temporary, fs::metadata(path)?.permissions()))
+        .and_then(|_| fs::rename(&temporary, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary);
     }
+    written
 }
 
 fn main() {
     let args: Args = Docopt::new(USAGE)
-        .and_then(|dopt| dopt.decode())
+        .and_then(|dopt| dopt.deserialize())
         .unwrap_or_else(|e| e.exit());
+    if args.flag_version {
+        println!("subpar {}", env!("CARGO_PKG_VERSION"));
+        return;
+    }
+    validate(&args);
 
-    let mut input = String::new();
-    if io::stdin().read_to_string(&mut input).is_ok() {
-        for paragraph in &tokenize(&input) {
-            println!("{}", reformat(&paragraph, &args));
+    let config = config::read();
+    let mut width = match args.flag_width {
+        Some(ref width) => parse_width(width),
+        None => default_width(config.width.unwrap_or(DEFAULT_WIDTH)),
+    };
+    if width == 0 {
+        width = terminal_width().unwrap_or(DEFAULT_WIDTH);
+    }
+
+    let mut options = Options::new(width, args.flag_last || config.last == Some(true));
+    if let Some(goal) = args.flag_goal {
+        if goal > width {
+            usage_error("The goal may not exceed the width.");
+        }
+        options.goal = goal;
+    }
+    if args.flag_knuth {
+        options.algorithm = Algorithm::KnuthPlass;
+    } else if args.flag_greedy || args.flag_prose_wrap {
+        options.algorithm = Algorithm::Greedy;
+    }
+    options.avoid_rivers = args.flag_avoid_rivers;
+    if args.flag_justify {
+        options.align = Align::Justify;
+    } else if args.flag_center {
+        options.align = Align::Center;
+    } else if args.flag_right {
+        options.align = Align::Right;
+    } else if let Some(ref align) = config.align {
+        options.align = match &align[..] {
+            "left" => Align::Left,
+            "justify" => Align::Justify,
+            "center" => Align::Center,
+            "right" => Align::Right,
+            _ => {
+                eprintln!("subpar: Unknown alignment '{}' in the configuration.", align);
+                process::exit(1);
+            }
+        };
+    }
+
+    if args.flag_penalty_exp == 0 {
+        usage_error("The penalty exponent must be at least 1.");
+    }
+    options.penalty_exponent = args.flag_penalty_exp;
+    options.overflow_penalty = args.flag_overflow_penalty;
+    options.last_line_slack = args.flag_last_line_slack;
+    options.last_reward = match args.flag_last_reward {
+        Some(_) if args.flag_last_line_slack > 0 => {
+            usage_error("The options --last-line-slack and --last-reward cannot go together.")
+        }
+        Some(0) => usage_error("The reward of the last line must be at least 1."),
+        reward => reward,
+    };
+    if !(0.0..=1.0).contains(&args.flag_last_min_frac) {
+        usage_error("The share of the width must be between 0 and 1.");
+    }
+    options.last_min_frac = args.flag_last_min_frac;
+    options.glue_width = match args.flag_glue_width {
+        Some(glue) if !(glue >= 0.0 && glue.is_finite()) => {
+            usage_error("The glue width may not be negative.")
+        }
+        glue => glue,
+    };
+    options.min_last = args.flag_min_last;
+    options.widows = !args.flag_no_widows;
+    if args.flag_lines == Some(0) {
+        usage_error("A paragraph has at least one line.");
+    }
+    options.lines = args.flag_lines;
+    if args.flag_tab_width == 0 {
+        usage_error("The tab width must be at least 1.");
+    }
+    options.tab_width = args.flag_tab_width;
+    options.debug = args.flag_debug;
+    options.keep_trailing = args.flag_keep_trailing;
+    options.pad = args.flag_pad;
+    options.squeeze = args.flag_squeeze;
+    options.sentence_lines = args.flag_sentences;
+    options.verse = args.flag_verse;
+    options.only_long = args.flag_only_long;
+    options.verify = args.flag_verify;
+    options.flush_on_blank = args.flag_flush_on_blank;
+    options.jobs = match args.flag_jobs {
+        0 => thread::available_parallelism().map_or(1, |n| n.get()),
+        n => n,
+    };
+    options.hang = args.flag_hang;
+    options.indent_tabs = match args.flag_indent_char.as_deref() {
+        None => None,
+        Some("tab") => Some(true),
+        Some("space") => Some(false),
+        Some(c) => usage_error(&format!("Unknown indent character '{}'.", c)),
+    };
+    options.first_indent = args.flag_indent;
+    options.lossy = args.flag_lossy;
+    let encodings = Encodings {
+        from: args.flag_from_encoding.as_deref().map(encoding),
+        to: args.flag_to_encoding.as_deref().map(encoding),
+        lossy: args.flag_lossy,
+    };
+    options.final_newline = !args.flag_no_final_newline;
+    options.paragraph_separator = args.flag_para_sep;
+    options.squeeze_blanks = args.flag_squeeze_blanks;
+    if args.flag_crlf {
+        options.crlf = Some(true);
+    }
+    let sentence_spaces = args.flag_sentence_spaces
+        .or(config.sentence_spaces)
+        .unwrap_or(if args.flag_prose_wrap { 1 } else { 2 });
+    if sentence_spaces == 0 {
+        usage_error("Sentences must be followed by at least one space.");
+    }
+    options.sentence_spaces = sentence_spaces;
+    options.sentences = !args.flag_no_sentences;
+    options.single_space_sentences = args.flag_single_space_sentences;
+    options.sentence_ends = args.flag_sentence_ends;
+    if let Some(file) = args.flag_abbrev {
+        match fs::read_to_string(&file) {
+            Ok(words) => {
+                options.abbreviations.extend(words.split_whitespace().map(str::to_string))
+            }
+            Err(_) => usage_error(&format!("Cannot read the abbreviations from '{}'.", file)),
+        }
+    }
+    options.prefix = args.flag_prefix;
+    options.auto_prefix = args.flag_auto_prefix;
+    options.quotes = args.flag_quotes;
+    options.markdown = args.flag_markdown || args.flag_prose_wrap;
+    options.prose_wrap = args.flag_prose_wrap;
+    options.rst = args.flag_rst;
+    options.definitions = args.flag_definitions || args.flag_wrap_after_colon;
+    if args.flag_wrap_after_colon && !args.flag_definitions {
+        options.max_term_width = Some(LABEL_WIDTH);
+    }
+    options.renumber = args.flag_renumber;
+    options.width_mode = match &args.flag_width_mode[..] {
+        "columns" => WidthMode::Columns,
+        "graphemes" => WidthMode::Graphemes,
+        "chars" => WidthMode::Chars,
+        mode => usage_error(&format!("Unknown width mode '{}'.", mode)),
+    };
+    if args.flag_graphemes {
+        options.width_mode = WidthMode::Graphemes;
+    }
+    options.normalization = match &args.flag_normalize[..] {
+        "none" => None,
+        "nfc" => Some(Normalization::Nfc),
+        "nfd" => Some(Normalization::Nfd),
+        form => usage_error(&format!("Unknown normalization form '{}'.", form)),
+    };
+    options.ansi = args.flag_ansi;
+    options.dehyphenate = args.flag_dehyphen;
+    options.break_dashes = args.flag_break_dashes;
+    options.fix_punct_spacing = args.flag_fix_punct_spacing;
+    options.protect_urls = !args.flag_break_urls;
+    if let Some(pattern) = args.flag_keep_together {
+        match Regex::new(&pattern) {
+            Ok(regex) => options.keep_together = Some(regex),
+            Err(e) => usage_error(&format!("Invalid pattern for --keep-together: {}", e)),
+        }
+    }
+    options.french_spacing = args.flag_french_spacing;
+    if let Some(code) = args.flag_hyphenate {
+        match Language::try_from_code(&code) {
+            Some(language) => options.hyphenate = Some(language),
+            None => usage_error(&format!("Unknown hyphenation language '{}'.", code)),
+        }
+    }
+
+    if args.flag_ruler {
+        eprintln!("{}", ruler(width));
+    }
+    if args.flag_check {
+        let formatted = inputs(&args.arg_file, encodings)
+            .iter()
+            .all(|(_, input)| subpar::reformat_with(input, &options) == *input);
+        process::exit(if formatted { 0 } else { 1 });
+    } else if args.flag_diff {
+        let mut changed = false;
+        for (name, input) in inputs(&args.arg_file, encodings) {
+            let output = subpar::reformat_with(&input, &options);
+            if output != input {
+                let diff = TextDiff::from_lines(&input, &output);
+                print!("{}", diff.unified_diff().header(&name, &name));
+                changed = true;
+            }
+        }
+        process::exit(if changed { 1 } else { 0 });
+    } else if args.flag_in_place {
+        if args.arg_file.is_empty() {
+            usage_error("Editing in place requires at least one file.");
+        }
+        if args.flag_output.is_some() {
+            usage_error("Editing in place writes no other output.");
+        }
+        for file in &args.arg_file {
+            if let Err(e) = edit_in_place(file, &options, encodings) {
+                eprintln!("subpar: {}: {}", file, e);
+                process::exit(1);
+            }
         }
     } else {
-        println!("subpar: Error reading stdin.");
+        let stream = args.flag_stream || args.flag_flush_on_blank;
+        if stream && !args.arg_file.is_empty() {
+            usage_error("Streaming reads the standard input only.");
+        }
+        if stream && (encodings.from.is_some() || encodings.to.is_some()) {
+            usage_error("Streaming reads and writes UTF-8 only.");
+        }
+        // The input is read before the output is created, which may be
+        // one of the input files.
+        let input = if stream && !args.flag_json && !args.flag_measure {
+            None
+        } else {
+            Some(read_input(&args.arg_file, encodings))
+        };
+        let (name, out): (&str, Box<dyn Write>) = match args.flag_output {
+            Some(ref path) => match File::create(path) {
+                Ok(file) => (path, Box::new(file)),
+                Err(e) => {
+                    eprintln!("subpar: {}: {}", path, e);
+                    process::exit(1);
+                }
+            },
+            None => {
+                options.color = options.debug && io::stdout().is_terminal();
+                ("stdout", Box::new(io::stdout()))
+            }
+        };
+        let mut out = BufWriter::new(out);
+        // The output is transcoded as a whole.
+        let mut utf8 = Vec::new();
+        let mut target: &mut dyn Write = if encodings.to.is_some() { &mut utf8 } else { &mut out };
+        let written = match input {
+            Some(input) if args.flag_json => {
+                target.write_all(subpar::to_json(&input, &options).as_bytes())
+            }
+            Some(input) if args.flag_measure => {
+                target.write_all(subpar::measure(&input, &options).as_bytes())
+            }
+            Some(input) => subpar::reformat_to(&input, &options, &mut target),
+            None => subpar::reformat_stream(io::stdin().lock(), &options, &mut target),
+        };
+        let written = written.and_then(|_| {
+            if encodings.to.is_some() {
+                out.write_all(&encodings.encode(utf8))?;
+            }
+            out.flush()
+        });
+        if let Err(e) = written {
+            // Only the input, when streamed, may not be UTF-8.
+            let name = if e.kind() == io::ErrorKind::InvalidData { "<stdin>" } else { name };
+            eprintln!("subpar: {}: {}", name, e);
+            process::exit(1);
+        }
     }
 }
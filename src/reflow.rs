@@ -0,0 +1,3106 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::ptr;
+use termcolor::{Ansi, Color, ColorSpec, WriteColor};
+use hyphenation::{Hyphenator, Load, Standard};
+pub use hyphenation::Language;
+use regex::Regex;
+use unicode_normalization::{is_nfc, is_nfd, UnicodeNormalization};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// Unicode normalization forms.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Normalization {
+    // Composed: "é" is a single code point when there is one.
+    Nfc,
+    // Decomposed: "é" is e followed by U+0301.
+    Nfd,
+}
+
+// How the length of a piece of text is measured.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WidthMode {
+    // Terminal columns: wide characters (e.g. CJK) count as two, combining
+    // marks as zero.
+    Columns,
+    // User-perceived characters (extended grapheme clusters): "é" written
+    // as e + U+0301, or a flag emoji, counts as one.
+    Graphemes,
+    // Code points, whatever their appearance.
+    Chars,
+}
+
+// Measures every piece of text: the line breaking, the justification and
+// the ruler of --debug all agree.
+pub fn display_width(s: &str, mode: WidthMode) -> usize {
+    match mode {
+        WidthMode::Columns => UnicodeWidthStr::width(s),
+        WidthMode::Graphemes => s.graphemes(true).count(),
+        WidthMode::Chars => s.chars().count(),
+    }
+}
+
+// Removes the ANSI escape sequences (colors and other terminal controls)
+// from `s`.
+pub fn strip_ansi(s: &str) -> Cow<'_, str> {
+    if !s.contains('\x1b') {
+        return Cow::Borrowed(s);
+    }
+    let mut stripped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+        } else if chars.next() == Some('[') {
+            // Control sequence: parameters, then a final byte in @..~.
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    Cow::Owned(stripped)
+}
+
+// Measures the lines: the visible length of text, and the gaps between
+// words.
+#[derive(Debug, Clone, Copy)]
+pub struct Metric {
+    pub mode: WidthMode,
+    // Escape sequences take no room on screen.
+    pub ansi: bool,
+    // Number of spaces after the end of a sentence.
+    pub sentence_spaces: usize,
+    // Distance between the tab stops.
+    pub tab_width: usize,
+}
+
+impl Metric {
+    // Tabs reach the next tab stop, counting from the start of `s`.
+    pub fn width(&self, s: &str) -> usize {
+        if !s.contains('\t') {
+            return self.visible_width(s);
+        }
+        let mut width = 0;
+        for (k, part) in s.split('\t').enumerate() {
+            if k > 0 {
+                width = (width / self.tab_width + 1) * self.tab_width;
+            }
+            width += self.visible_width(part);
+        }
+        width
+    }
+
+    // Rewrites the whitespace of `indent` with tabs (as many as fit, then
+    // spaces) if `tabs` is set, or else with spaces, reaching the same
+    // columns.
+    pub fn reindent(&self, indent: &str, tabs: bool) -> String {
+        let mut reindented = String::new();
+        let mut done = 0;
+        while done < indent.len() {
+            let rest = &indent[done..];
+            let blank = rest.len() - rest.trim_start_matches([' ', '\t']).len();
+            if blank == 0 {
+                let c = rest.chars().next().expect("the rest is not empty");
+                reindented.push(c);
+                done += c.len_utf8();
+                continue;
+            }
+            let mut column = self.width(&indent[..done]);
+            let end = self.width(&indent[..done + blank]);
+            while tabs && (column / self.tab_width + 1) * self.tab_width <= end {
+                reindented.push('\t');
+                column = (column / self.tab_width + 1) * self.tab_width;
+            }
+            reindented.push_str(&" ".repeat(end - column));
+            done += blank;
+        }
+        reindented
+    }
+
+    fn visible_width(&self, s: &str) -> usize {
+        if self.ansi {
+            display_width(&strip_ansi(s), self.mode)
+        } else {
+            display_width(s, self.mode)
+        }
+    }
+
+    // Number of spaces between `word` and the next one on a line.  The line
+    // lengths, the rendering and the justification (which stretches these
+    // gaps, the sentence ones included) all rely on it.
+    pub fn gap(&self, word: &Word) -> usize {
+        match *word {
+            Word::Normal(_) | Word::Ambiguous(_) | Word::LineEnd(_) => 1,
+            Word::EndOfSentence(_) => self.sentence_spaces,
+            Word::Syllable(_) | Word::Dash(_) => 0,
+        }
+    }
+}
+
+// How the line breaks are chosen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Algorithm {
+    // Minimize the sum of the badness of the lines.
+    Badness,
+    // Minimize the demerits of Knuth and Plass, which also penalize
+    // consecutive lines of very different tightness.
+    KnuthPlass,
+    // Fill each line in turn (first fit), without looking ahead.
+    Greedy,
+}
+
+// Horizontal placement of the words of a line once the breaks are chosen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    // Pad the gaps of every line but the last one to reach the width.
+    Justify,
+    Center,
+    Right,
+}
+
+// Parameters of the line-breaking optimization.
+#[derive(Debug, Clone)]
+pub struct Options {
+    // Hard limit: lines longer than this are heavily penalized.
+    pub width: usize,
+    // Soft target the lines are optimized toward, at most `width`.
+    pub goal: usize,
+    pub last: bool,
+    pub algorithm: Algorithm,
+    pub align: Align,
+    // Comment marker stripped from the input lines and put back in front
+    // of the output lines.
+    pub prefix: Option<String>,
+    // Handle `>` quotation markers, as in emails.
+    pub quotes: bool,
+    pub width_mode: WidthMode,
+    // Ignore ANSI escape sequences when measuring the text.
+    pub ansi: bool,
+    // Join the words split by a hyphen at the end of a line, where the
+    // dictionary of `hyphenate` allows it.
+    pub dehyphenate: bool,
+    // Hyphenate the words according to the rules of this language.
+    pub hyphenate: Option<Language>,
+    // End the output lines with CRLF rather than LF.  If unset, the
+    // output follows the input.
+    pub crlf: Option<bool>,
+    // Number of spaces after the end of a sentence.
+    pub sentence_spaces: usize,
+    // Detect the ends of sentences, to space them more widely.
+    pub sentences: bool,
+    // Characters that end a sentence when they end a word.
+    pub sentence_ends: String,
+    // Words which do not end a sentence despite their final period.
+    pub abbreviations: Vec<String>,
+    // Follow every line with a ruler marking the width (see the `Debug`
+    // output of `Paragraph`).
+    pub debug: bool,
+    // Power of the distance of a line to the goal, and cost of each
+    // character past the width (see `Cost`).
+    pub penalty_exponent: u32,
+    pub overflow_penalty: usize,
+    // Length under which the last line of a paragraph costs something,
+    // unless `last` is set.
+    pub last_line_slack: usize,
+    // Make the last line cost as much as the others, but divided by this
+    // reward past the share `last_min_frac` of the width (see `Cost`).
+    pub last_reward: Option<usize>,
+    pub last_min_frac: f64,
+    // Never split the URLs and file paths, even to hyphenate them.
+    pub protect_urls: bool,
+    // Copy the code blocks of Markdown as they are.
+    pub markdown: bool,
+    // Indent of the lines of a paragraph after the first one.
+    pub hang: usize,
+    // Indent of the first line of a paragraph.
+    pub first_indent: usize,
+    // Number of threads reformatting the paragraphs.
+    pub jobs: usize,
+    // Guess the prefix from the first line, if none is given.
+    pub auto_prefix: bool,
+    // Length under which the last line of a paragraph takes words from the
+    // line before, unless it has two words already.
+    pub min_last: usize,
+    // Allow a single word on the last line of a paragraph.
+    pub widows: bool,
+    // Distance between the tab stops.
+    pub tab_width: usize,
+    // Keep the whitespace ending the last line of each paragraph, and the
+    // prefix of the blank lines, as it is.
+    pub keep_trailing: bool,
+    // Put narrow no-break spaces inside the French guillemets and before
+    // the high punctuation, as in « Quoi ? ».
+    pub french_spacing: bool,
+    // Allow breaking a line after the em dashes within words.
+    pub break_dashes: bool,
+    // Break each paragraph into at most this many lines, the longest one
+    // as short as possible, instead of filling them.
+    pub lines: Option<usize>,
+    // When justifying, keep the gaps of consecutive lines apart, so that no
+    // river of white runs down the paragraph.
+    pub avoid_rivers: bool,
+    // Take a sentence ending followed by a single space as such if the
+    // next word is capitalized.
+    pub single_space_sentences: bool,
+    // Runs of words matching this pattern, written with single spaces, are
+    // kept on the same line.
+    pub keep_together: Option<Regex>,
+    // Check that every line fits (or is a single word) and that no word is
+    // lost, warning on stderr otherwise.
+    pub verify: bool,
+    // Keep the line breaks of the input, only normalizing the spaces.
+    pub squeeze: bool,
+    // Never join the lines of the input, but still break those too long.
+    pub verse: bool,
+    // Wrap the lines starting with a term such as `key:` under the text
+    // following it, the term starting a paragraph of its own.
+    pub definitions: bool,
+    // Put a space after the commas, semicolons and colons glued to the next
+    // word (but for numbers such as 3,14 and for the URLs).
+    pub fix_punct_spacing: bool,
+    // Color the characters of the `debug` output past the width (for a
+    // terminal).
+    pub color: bool,
+    // End the output with a newline (the last paragraph being followed by
+    // a single one).
+    pub final_newline: bool,
+    // Written (as a line of its own, unless empty) instead of the blank
+    // lines between the paragraphs.
+    pub paragraph_separator: Option<String>,
+    // Replace the invalid UTF-8 of the input (e.g. Latin-1) instead of
+    // failing.
+    pub lossy: bool,
+    // Start every sentence on a line of its own (semantic line breaks),
+    // wrapping it if it is too long.
+    pub sentence_lines: bool,
+    // Pad every line with spaces to the width.
+    pub pad: bool,
+    // Number the items of each ordered list in sequence, from the number of
+    // the first one.
+    pub renumber: bool,
+    // Write the whitespace of the indents with tabs (as many as fit) if
+    // set, or with spaces only if unset.  If `None`, it is kept as is.
+    pub indent_tabs: Option<bool>,
+    // When streaming, write each paragraph (and flush the output) as soon
+    // as a blank line follows it, rather than with the next one.
+    pub flush_on_blank: bool,
+    // Unicode normalization form the input is put in first, if any.
+    pub normalization: Option<Normalization>,
+    // In Markdown, also keep the headings, thematic breaks, rows of tables
+    // and HTML blocks as they are, as Prettier does with `proseWrap`.
+    pub prose_wrap: bool,
+    // Keep the lines which fit as they are, only wrapping (each on its own)
+    // those which overflow.
+    pub only_long: bool,
+    // Copy the literal blocks, line blocks and explicit markup of
+    // reStructuredText as they are.
+    pub rst: bool,
+    // Widest term of the definitions (colon included), the lines starting
+    // with wider ones being wrapped as the others.
+    pub max_term_width: Option<usize>,
+    // Columns each space of the gaps between the words counts for in the
+    // line breaking (e.g. 0.33 to estimate a proportional font), the output
+    // keeping the spaces.  If `None`, a space is a column.
+    pub glue_width: Option<f64>,
+    // Write a single blank line wherever the input has several between two
+    // blocks (rather than as many).
+    pub squeeze_blanks: bool,
+}
+
+impl Options {
+    pub fn new(width: usize, last: bool) -> Options {
+        Options {
+            width,
+            goal: width,
+            last,
+            algorithm: Algorithm::Badness,
+            align: Align::Left,
+            prefix: None,
+            quotes: false,
+            width_mode: WidthMode::Columns,
+            ansi: false,
+            dehyphenate: false,
+            hyphenate: None,
+            crlf: None,
+            sentence_spaces: 2,
+            sentences: true,
+            sentence_ends: ".!?…".to_string(),
+            abbreviations: ABBREVIATIONS.iter().map(|a| a.to_string()).collect(),
+            debug: false,
+            penalty_exponent: 3,
+            overflow_penalty: 1_000_000,
+            last_line_slack: 0,
+            last_reward: None,
+            last_min_frac: 0.25,
+            protect_urls: true,
+            markdown: false,
+            hang: 0,
+            first_indent: 0,
+            jobs: 1,
+            auto_prefix: false,
+            min_last: 0,
+            widows: true,
+            tab_width: 8,
+            keep_trailing: false,
+            french_spacing: false,
+            break_dashes: false,
+            lines: None,
+            avoid_rivers: false,
+            single_space_sentences: false,
+            keep_together: None,
+            verify: false,
+            squeeze: false,
+            verse: false,
+            definitions: false,
+            fix_punct_spacing: false,
+            color: false,
+            final_newline: true,
+            paragraph_separator: None,
+            lossy: false,
+            sentence_lines: false,
+            pad: false,
+            renumber: false,
+            indent_tabs: None,
+            flush_on_blank: false,
+            normalization: None,
+            prose_wrap: false,
+            only_long: false,
+            rst: false,
+            max_term_width: None,
+            glue_width: None,
+            squeeze_blanks: false,
+        }
+    }
+
+    pub fn metric(&self) -> Metric {
+        Metric {
+            mode: self.width_mode,
+            ansi: self.ansi,
+            sentence_spaces: self.sentence_spaces,
+            tab_width: self.tab_width,
+        }
+    }
+
+    pub fn cost(&self) -> Cost {
+        Cost {
+            exponent: self.penalty_exponent,
+            overflow: self.overflow_penalty,
+            slack: self.last_line_slack,
+            last_reward: self.last_reward,
+            last_min_frac: self.last_min_frac,
+        }
+    }
+}
+
+// Words ending with a period which do not end a sentence.
+pub const ABBREVIATIONS: &[&str] = &["Mr.", "Dr.", "etc.", "vs.", "Fig.", "No."];
+
+// Cost of breaking a line inside a word.
+const HYPHEN_PENALTY: usize = 1_000;
+// Cost of breaking a line after an `Ambiguous` word.
+const AMBIGUOUS_PENALTY: usize = 100_000;
+
+#[derive(Debug)]
+pub enum Word<'a> {
+    Normal(Cow<'a, str>),
+    EndOfSentence(Cow<'a, str>),
+    // Word ending like a sentence but followed by a single space.  Were a
+    // line to end with it, it would be read as the end of a sentence the
+    // next time, so this is avoided.
+    Ambiguous(Cow<'a, str>),
+    // Part of a word, glued to the next one unless the line is broken
+    // after it, in which case it takes a hyphen.
+    Syllable(Cow<'a, str>),
+    // Part of a word ending with an em dash, glued to the next one unless
+    // the line is broken after it.
+    Dash(Cow<'a, str>),
+    // Last word before a hard line break: the line must end after it.
+    LineEnd(Cow<'a, str>),
+}
+
+impl<'a> Word<'a> {
+    pub fn text(&self) -> &str {
+        self.inner()
+    }
+
+    fn inner(&self) -> &Cow<'a, str> {
+        match *self {
+            Word::Normal(ref w) |
+            Word::EndOfSentence(ref w) |
+            Word::Ambiguous(ref w) |
+            Word::Syllable(ref w) |
+            Word::Dash(ref w) |
+            Word::LineEnd(ref w) => w,
+        }
+    }
+
+    // The part of the text from byte `start` to byte `end`, borrowed from
+    // the input if the text is.
+    fn slice(&self, start: usize, end: usize) -> Cow<'a, str> {
+        match *self.inner() {
+            Cow::Borrowed(s) => Cow::Borrowed(&s[start..end]),
+            Cow::Owned(ref s) => Cow::Owned(s[start..end].to_string()),
+        }
+    }
+
+    // What is written after this word when a line ends with it.
+    pub fn break_text(&self) -> &'static str {
+        match *self {
+            Word::Syllable(_) => "-",
+            Word::Normal(_) |
+            Word::EndOfSentence(_) |
+            Word::Ambiguous(_) |
+            Word::Dash(_) |
+            Word::LineEnd(_) => "",
+        }
+    }
+
+    // Extra cost of ending a line with this word.
+    pub fn penalty(&self) -> usize {
+        match *self {
+            Word::Syllable(_) => HYPHEN_PENALTY,
+            Word::Ambiguous(_) => AMBIGUOUS_PENALTY,
+            Word::Normal(_) | Word::EndOfSentence(_) | Word::Dash(_) | Word::LineEnd(_) => 0,
+        }
+    }
+
+    // The same kind of word, made of `text`.
+    fn with_text<'b>(&self, text: Cow<'b, str>) -> Word<'b> {
+        match *self {
+            Word::Normal(_) => Word::Normal(text),
+            Word::EndOfSentence(_) => Word::EndOfSentence(text),
+            Word::Ambiguous(_) => Word::Ambiguous(text),
+            Word::Syllable(_) => Word::Syllable(text),
+            Word::Dash(_) => Word::Dash(text),
+            Word::LineEnd(_) => Word::LineEnd(text),
+        }
+    }
+
+    pub fn data(&self, metric: Metric) -> WordData {
+        WordData {
+            text: self.text().to_string(),
+            kind: match *self {
+                Word::Normal(_) => "Normal",
+                Word::EndOfSentence(_) => "EndOfSentence",
+                Word::Ambiguous(_) => "Ambiguous",
+                Word::Syllable(_) => "Syllable",
+                Word::Dash(_) => "Dash",
+                Word::LineEnd(_) => "LineEnd",
+            },
+            width: metric.width(self.text()),
+        }
+    }
+}
+
+// Owned copies of the words and blocks, to be serialized (e.g. as JSON).
+#[derive(Debug, Serialize)]
+pub struct WordData {
+    pub text: String,
+    pub kind: &'static str,
+    pub width: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockData {
+    pub indent: String,
+    pub first: Option<String>,
+    pub quote: usize,
+    pub blank_lines: usize,
+    pub words: Vec<WordData>,
+    pub verbatim: Vec<String>,
+}
+
+// A paragraph of the input: its words, and what precedes them on each
+// line (the comment prefix if any, the quote markers, and the leading
+// whitespace shared by all its lines).
+#[derive(Debug)]
+pub struct Block<'a> {
+    pub indent: String,
+    // What precedes the first line instead, if it differs (e.g. the marker
+    // of a list item).
+    pub first: Option<String>,
+    // Quotation depth, i.e. number of leading `>`.
+    pub quote: usize,
+    // Number of blank lines separating the block from the previous one.
+    pub blank_lines: usize,
+    pub words: Vec<Word<'a>>,
+    // Lines copied as they are (the code blocks of Markdown), in which case
+    // there are no words.
+    pub verbatim: Vec<&'a str>,
+    // Width of the lines set by the last directive, if any, instead of the
+    // one of the options.
+    pub width: Option<usize>,
+    // Marker of the list item (ending `first`), if the block is one.
+    marker: Option<&'a str>,
+}
+
+impl<'a> Block<'a> {
+    fn new(prefix: Option<String>,
+           quote: usize,
+           indent: Option<&str>,
+           blank_lines: usize,
+           words: Vec<Word<'a>>)
+           -> Block<'a> {
+        Block {
+            indent: prefix.unwrap_or_default() + &"> ".repeat(quote) + indent.unwrap_or(""),
+            first: None,
+            quote,
+            blank_lines,
+            words,
+            verbatim: Vec::new(),
+            width: None,
+            marker: None,
+        }
+    }
+
+    // Makes the block a list item: `marker` starts its first line, and the
+    // others are aligned with the text after it.
+    fn item(mut self, marker: Option<&'a str>) -> Block<'a> {
+        if let Some(marker) = marker {
+            self.first = Some(self.indent.clone() + marker);
+            self.indent += &" ".repeat(marker.chars().count());
+            self.marker = Some(marker);
+        }
+        self
+    }
+
+    pub fn data(&self, metric: Metric) -> BlockData {
+        BlockData {
+            indent: self.indent.clone(),
+            first: self.first.clone(),
+            quote: self.quote,
+            blank_lines: self.blank_lines,
+            words: self.words.iter().map(|w| w.data(metric)).collect(),
+            verbatim: self.verbatim.iter().map(|l| l.to_string()).collect(),
+        }
+    }
+
+    fn verbatim(blank_lines: usize, line: &'a str) -> Block<'a> {
+        Block {
+            indent: String::new(),
+            first: None,
+            quote: 0,
+            blank_lines,
+            words: Vec::new(),
+            verbatim: vec![line],
+            width: None,
+            marker: None,
+        }
+    }
+}
+
+// Renumbers the items of the ordered lists in `blocks`, in sequence from
+// the first one of each list.  `levels` holds the indents of the lists
+// under way, outermost first, with the next number of each; a list ends
+// with a block less indented than its items' text.
+pub fn renumber(blocks: &mut [Block], levels: &mut Vec<(String, usize)>) {
+    for block in blocks.iter_mut().filter(|b| b.verbatim.is_empty()) {
+        let marker = match block.marker {
+            Some(marker) => marker,
+            None => {
+                levels.retain(|(indent, _)| indent.len() < block.indent.len());
+                continue;
+            }
+        };
+        let first = block.first.take().unwrap_or_default();
+        let base = first[..first.len() - marker.len()].to_string();
+        levels.retain(|(indent, _)| indent.len() <= base.len());
+        let digits = marker.len() - marker.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let number = match marker[..digits].parse::<usize>() {
+            Ok(number) => number,
+            Err(_) => {
+                // A bullet ends the ordered list at the same indent.
+                levels.retain(|(indent, _)| indent.len() < base.len());
+                block.first = Some(first);
+                continue;
+            }
+        };
+        let number = match levels.last_mut() {
+            Some(&mut (ref indent, ref mut next)) if *indent == base => {
+                *next += 1;
+                *next - 1
+            }
+            _ => {
+                levels.push((base.clone(), number + 1));
+                number
+            }
+        };
+        let marker = number.to_string() + &marker[digits..];
+        block.indent = base.clone() + &" ".repeat(marker.len());
+        block.first = Some(base + &marker);
+    }
+}
+
+// Tells whether `c` separates words: any whitespace but the non-breaking
+// spaces, which glue their neighbours into a single word.
+pub fn is_space(c: char) -> bool {
+    c.is_whitespace() && !['\u{a0}', '\u{2007}', '\u{202f}'].contains(&c)
+}
+
+// Returns the longest common prefix of `a` and `b`.
+pub fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let mut end = 0;
+    for ((i, x), y) in a.char_indices().zip(b.chars()) {
+        if x != y {
+            break;
+        }
+        end = i + x.len_utf8();
+    }
+    &a[..end]
+}
+
+// Guesses the comment prefix of `input` from its first non-blank line:
+// a known comment marker, and the space following it if any.
+pub fn detect_prefix(input: &str) -> Option<String> {
+    let markers = ["///", "//!", "//", "#", "--", ";;", ";", "%"];
+    let line = input.lines().map(str::trim_start).find(|l| !l.is_empty())?;
+    let marker = markers.iter().find(|m| line.starts_with(**m))?;
+    if line[marker.len()..].starts_with(' ') {
+        Some(format!("{} ", marker))
+    } else {
+        Some(marker.to_string())
+    }
+}
+
+// Splits off the comment `prefix` of `line` if it carries one (possibly
+// indented, or without its trailing whitespace).  Returns what precedes
+// the text in the output, if the prefix was found, and the text.
+fn strip_prefix<'a>(line: &'a str, prefix: &str) -> (Option<String>, &'a str) {
+    let content = line.trim_start_matches(is_space);
+    let outer = &line[..line.len() - content.len()];
+    let marker = prefix.trim_end_matches(is_space);
+    if marker.is_empty() {
+        return (None, line);
+    }
+    match content.strip_prefix(prefix).or_else(|| content.strip_prefix(marker)) {
+        Some(rest) => (Some(outer.to_string() + prefix), rest),
+        None => (None, line),
+    }
+}
+
+// Splits off the leading `>` markers of `line` (with the space following
+// them).  Returns the quotation depth and the rest of the line.
+fn strip_quotes(line: &str) -> (usize, &str) {
+    let mut depth = 0;
+    let mut rest = line;
+    while let Some(r) = rest.trim_start().strip_prefix('>') {
+        depth += 1;
+        rest = r;
+    }
+    if depth > 0 {
+        rest = rest.strip_prefix(' ').unwrap_or(rest);
+    }
+    (depth, rest)
+}
+
+// Joins `word`, the last word of a line ending with a hyphen, with the
+// first word of the next line `next` if it looks like a word split by
+// hyphenation: both parts in lowercase, which leaves out most names.  A
+// hyphen is dropped only if `dictionary` hyphenates the joined word there:
+// anything else may be a compound (e.g. well-known), which keeps it.  The
+// other hyphens of `word`, kept from the lines before (e.g. in-/ci-/didunt,
+// where in-ci was too short to tell), are checked again likewise.
+fn dehyphenate(word: &str, next: &str, dictionary: Option<&Standard>) -> Option<String> {
+    let stem = word.strip_suffix('-')?;
+    if stem.split('-').any(|part| part.is_empty() || !part.chars().all(char::is_lowercase)) ||
+       !next.starts_with(char::is_lowercase) {
+        return None;
+    }
+    let plain = stem.replace('-', "") + next;
+    let breaks = dictionary.map_or(Vec::new(), |d| {
+        d.hyphenate(plain.trim_end_matches(|c: char| !c.is_alphabetic())).breaks
+    });
+    let mut joined = String::with_capacity(word.len() + next.len());
+    let mut length = 0;
+    for part in stem.split('-') {
+        joined.push_str(part);
+        length += part.len();
+        if !breaks.contains(&length) {
+            joined.push('-');
+        }
+    }
+    joined.push_str(next);
+    Some(joined)
+}
+
+// Punctuation preceded by a space in French.
+const HIGH_PUNCTUATION: &str = ";:!?»";
+const NARROW_SPACE: char = '\u{202f}';
+
+// Joins `word` and `next`, separated by a space, if French typography
+// wants them on the same line: `next` starts with high punctuation, or
+// `word` ends with an opening guillemet.
+fn french_join(word: &str, next: &str) -> Option<String> {
+    if next.starts_with(|c| HIGH_PUNCTUATION.contains(c)) || word.ends_with('«') {
+        Some(format!("{}{}{}", word, NARROW_SPACE, next))
+    } else {
+        None
+    }
+}
+
+// Puts a narrow no-break space before the high punctuation ending `word`
+// (e.g. "quoi?!") and after an opening guillemet, or turns the no-break
+// space already there into a narrow one.
+fn french_spacing(word: &str) -> Cow<'_, str> {
+    if is_url(word) {
+        return Cow::Borrowed(word);
+    }
+    let spaced = |c: char| c == '\u{a0}' || c == NARROW_SPACE;
+    let high = |c: char| HIGH_PUNCTUATION.contains(c);
+    let chars: Vec<char> = word.chars().collect();
+    let mut spacing = String::with_capacity(word.len() + 6);
+    for (i, &c) in chars.iter().enumerate() {
+        let previous = if i > 0 { Some(chars[i - 1]) } else { None };
+        let next = chars.get(i + 1).cloned();
+        if spaced(c) && (next.is_some_and(high) || previous == Some('«')) {
+            spacing.push(NARROW_SPACE);
+            continue;
+        }
+        // Only the punctuation ending the word is spaced, which leaves out
+        // e.g. the times (10:30).
+        if high(c) && previous.is_some_and(|p| !high(p) && !spaced(p) && p != '«') &&
+           !chars[i..].iter().any(|c| c.is_alphanumeric()) {
+            spacing.push(NARROW_SPACE);
+        }
+        spacing.push(c);
+        if c == '«' && next.is_some_and(|n| !spaced(n)) {
+            spacing.push(NARROW_SPACE);
+        }
+    }
+    if spacing == word {
+        Cow::Borrowed(word)
+    } else {
+        Cow::Owned(spacing)
+    }
+}
+
+// Tells whether `word` looks like a URL or a file path, which must be
+// kept whole.
+pub fn is_url(word: &str) -> bool {
+    word.contains("://") || word.starts_with("www.") ||
+    (word.contains('/') && word.contains(char::is_alphanumeric))
+}
+
+// Splits the words into syllables at their soft hyphens (U+00AD), which
+// are dropped, or else wherever `dictionary` allows it.  Only the letters
+// are hyphenated, not the punctuation around them, and the URLs not at
+// all if `urls` is set.
+fn syllables<'a>(words: Vec<Word<'a>>,
+                 dictionary: Option<&Standard>,
+                 urls: bool)
+                 -> Vec<Word<'a>> {
+    let mut syllables = Vec::with_capacity(words.len());
+    for word in words {
+        // Byte ranges of the syllables of the word.
+        let ranges = {
+            let text = word.text();
+            let mut ranges = Vec::new();
+            let mut start = 0;
+            if urls && is_url(text) {
+                // Kept whole.
+            } else if text.contains('\u{ad}') {
+                for (i, shy) in text.match_indices('\u{ad}') {
+                    ranges.push((start, i));
+                    start = i + shy.len();
+                }
+            } else if let Some(dictionary) = dictionary {
+                let core = text.trim_matches(|c: char| !c.is_alphabetic());
+                let offset = text.len() -
+                             text.trim_start_matches(|c: char| !c.is_alphabetic()).len();
+                for b in dictionary.hyphenate(core).breaks {
+                    ranges.push((start, b + offset));
+                    start = b + offset;
+                }
+            }
+            if ranges.is_empty() {
+                syllables.push(word);
+                continue;
+            }
+            ranges.push((start, text.len()));
+            ranges.retain(|&(a, b)| a < b);
+            ranges
+        };
+        let last = ranges.len().saturating_sub(1);
+        for (k, &(a, b)) in ranges.iter().enumerate() {
+            let part = word.slice(a, b);
+            syllables.push(if k < last { Word::Syllable(part) } else { word.with_text(part) });
+        }
+    }
+    syllables
+}
+
+// Splits the words after their commas, semicolons and colons followed by
+// a letter or a digit (but between two digits, as in 3,14 or 10:30), the
+// URLs being left alone.
+fn punctuation(words: Vec<Word>) -> Vec<Word> {
+    let mut split = Vec::with_capacity(words.len());
+    for word in words {
+        let text = word.text();
+        let ends: Vec<usize> = if is_url(text) {
+            Vec::new()
+        } else {
+            text.match_indices([',', ';', ':'])
+                .filter(|&(i, _)| i > 0)
+                .filter(|&(i, _)| {
+                    let before = text[..i].chars().next_back();
+                    let after = text[i + 1..].chars().next();
+                    after.is_some_and(char::is_alphanumeric) &&
+                    !(before.is_some_and(|c| c.is_ascii_digit()) &&
+                      after.is_some_and(|c| c.is_ascii_digit()))
+                })
+                .map(|(i, _)| i + 1)
+                .collect()
+        };
+        if ends.is_empty() {
+            split.push(word);
+            continue;
+        }
+        let mut start = 0;
+        for end in ends {
+            split.push(Word::Normal(word.slice(start, end)));
+            start = end;
+        }
+        split.push(word.with_text(word.slice(start, word.text().len())));
+    }
+    split
+}
+
+// Splits the words after their em dashes, or runs of them, but for those
+// starting or ending the words (and the URLs if they are to be kept
+// whole), so that the lines may break there.
+fn dashes<'a>(words: Vec<Word<'a>>, urls: bool) -> Vec<Word<'a>> {
+    let mut split = Vec::with_capacity(words.len());
+    for word in words {
+        let ends: Vec<usize> = {
+            let text = word.text().trim_end_matches('\u{2014}');
+            if urls && is_url(text) {
+                Vec::new()
+            } else {
+                text.match_indices('\u{2014}')
+                    .map(|(i, dash)| i + dash.len())
+                    .filter(|&end| !text[end..].starts_with('\u{2014}'))
+                    .collect()
+            }
+        };
+        if ends.is_empty() {
+            split.push(word);
+            continue;
+        }
+        let text = word.text();
+        let mut start = 0;
+        for end in ends {
+            if end > start && !text[start..end].chars().all(|c| c == '\u{2014}') {
+                split.push(Word::Dash(word.slice(start, end)));
+                start = end;
+            }
+        }
+        split.push(word.with_text(word.slice(start, text.len())));
+    }
+    split
+}
+
+// Merges the runs of `words` matching `pattern` into single words, which
+// no line break can split.  The words are matched separated by single
+// spaces, whole (not split into syllables), and merged with their gaps.
+fn keep_together<'a>(words: Vec<Word<'a>>, pattern: &Regex, metric: Metric) -> Vec<Word<'a>> {
+    let mut text = String::new();
+    let mut ranges = Vec::with_capacity(words.len());
+    for word in &words {
+        let start = text.len();
+        text.push_str(word.text());
+        ranges.push((start, text.len()));
+        if metric.gap(word) > 0 {
+            text.push(' ');
+        }
+    }
+    // Ranges of the words to merge, which may not go past a hard break.
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for found in pattern.find_iter(&text).filter(|m| !m.is_empty()) {
+        let first = ranges.partition_point(|&(_, end)| end <= found.start());
+        let mut last = ranges.partition_point(|&(start, _)| start < found.end());
+        if let Some(k) = words[first..last].iter().position(|w| matches!(w, Word::LineEnd(_))) {
+            last = first + k + 1;
+        }
+        match groups.last_mut() {
+            Some(group) if first < group.1 => group.1 = group.1.max(last),
+            _ if last > first + 1 => groups.push((first, last)),
+            _ => {}
+        }
+    }
+    if groups.is_empty() {
+        return words;
+    }
+
+    let mut merged = Vec::with_capacity(words.len());
+    let mut groups = groups.into_iter().peekable();
+    let mut joined: Option<(usize, String)> = None;
+    for (k, word) in words.into_iter().enumerate() {
+        if joined.is_none() && groups.peek().is_some_and(|&(first, _)| first == k) {
+            joined = groups.next().map(|(_, last)| (last, String::new()));
+        }
+        match joined {
+            Some((last, ref mut text)) if k + 1 < last => {
+                text.push_str(word.text());
+                text.push_str(&" ".repeat(metric.gap(&word)));
+            }
+            Some((_, ref mut text)) => {
+                text.push_str(word.text());
+                merged.push(word.with_text(Cow::Owned(mem::take(text))));
+                joined = None;
+            }
+            None => merged.push(word),
+        }
+    }
+    merged
+}
+
+// Glues each word ending with a backslash (read as a hard break at the end
+// of a line) to the next one, and each word which would start a block at
+// the start of a line (see `starts_block`) to the one before, so that no
+// line breaking changes how the output reads.  With `sentence_lines`, the
+// word after an `Ambiguous` one is glued to it as well, since a break there
+// would read as the end of a sentence (and start a line of its own) the
+// next time.
+fn glue<'a>(words: Vec<Word<'a>>, metric: Metric, options: &Options) -> Vec<Word<'a>> {
+    let mut glued: Vec<Word<'a>> = Vec::with_capacity(words.len());
+    for word in words {
+        let sticks = glued.last().is_some_and(|w| {
+            !matches!(*w, Word::LineEnd(_)) &&
+            (w.text().ends_with('\\') || starts_block(word.text(), metric, options) ||
+             options.sentence_lines && matches!(*w, Word::Ambiguous(_)))
+        });
+        match glued.pop() {
+            Some(previous) if sticks => {
+                let text = previous.text().to_string() + &" ".repeat(metric.gap(&previous)) +
+                           word.text();
+                glued.push(word.with_text(Cow::Owned(text)));
+            }
+            previous => {
+                glued.extend(previous);
+                glued.push(word);
+            }
+        }
+    }
+    glued
+}
+
+// Tells whether `word` may end a sentence: it ends with one of the
+// sentence endings, possibly followed by closing quotes or brackets, but
+// not with a period following a digit (as in a numbered list or a decimal
+// number), and it is not an abbreviation.
+fn ends_sentence(word: &str, options: &Options) -> bool {
+    let word = word.trim_end_matches(|c| "\"'»)]}\u{a0}\u{202f}".contains(c));
+    // Leaves out the emoticons, e.g. `:)`.
+    if !word.contains(char::is_alphanumeric) {
+        return false;
+    }
+    let mut chars = word.chars().rev();
+    match chars.next() {
+        Some('.') if chars.next().is_some_and(|c| c.is_ascii_digit()) => false,
+        Some(c) if options.sentence_ends.contains(c) => {
+            let word = word.trim_start_matches(|c| "\"'«([{\u{a0}\u{202f}".contains(c));
+            !options.abbreviations.iter().any(|a| a == word)
+        }
+        _ => false,
+    }
+}
+
+// Returns the opening fence of a Markdown code block starting `line`,
+// i.e. at least three backticks or tildes, possibly followed by the
+// language of the code.
+pub fn opening_fence(line: &str) -> Option<&str> {
+    let line = line.strip_prefix("   ").or_else(|| line.strip_prefix("  "))
+        .or_else(|| line.strip_prefix(' '))
+        .unwrap_or(line);
+    let c = line.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let run = line.len() - line.trim_start_matches(c).len();
+    if run >= 3 { Some(&line[..run]) } else { None }
+}
+
+// Tells whether `line` closes the code block opened by `fence`.
+pub fn closes(line: &str, fence: &str) -> bool {
+    opening_fence(line).is_some_and(|f| f.starts_with(fence)) &&
+    line.trim_start().trim_start_matches(&fence[..1]).trim().is_empty()
+}
+
+// Returns the marker of the Markdown list item starting `line` (after its
+// indent), with the spaces following it.
+fn list_marker(line: &str) -> Option<&str> {
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let rest = if digits > 0 {
+        line[digits..].strip_prefix(['.', ')'])?
+    } else {
+        line.strip_prefix(['-', '*', '+'])?
+    };
+    let text = rest.trim_start_matches(' ');
+    if text.len() < rest.len() && !text.is_empty() {
+        Some(&line[..line.len() - text.len()])
+    } else {
+        None
+    }
+}
+
+// Returns the term, such as `key:`, starting `line` (after its indent),
+// with the spaces following it.
+fn definition_term(line: &str) -> Option<&str> {
+    let term = line.split(is_space).next()?;
+    if !is_term(term) {
+        return None;
+    }
+    let rest = &line[term.len()..];
+    let text = rest.trim_start_matches(' ');
+    if text.len() < rest.len() && !text.is_empty() {
+        Some(&line[..line.len() - text.len()])
+    } else {
+        None
+    }
+}
+
+// Tells whether `line` is a Markdown line kept as it is with `prose_wrap`:
+// `Some(true)` if it is the underline of a heading (of the paragraph before
+// it), `Some(false)` if it is a heading, a thematic break or the row of a
+// table.
+fn line_block(line: &str, in_paragraph: bool) -> Option<bool> {
+    let line = line.strip_prefix("   ").or_else(|| line.strip_prefix("  "))
+        .or_else(|| line.strip_prefix(' '))
+        .unwrap_or(line);
+    let text = line.trim_end_matches(is_space);
+    let c = text.chars().next()?;
+    if in_paragraph && (c == '=' || c == '-') && text.len() == text.matches(c).count() {
+        return Some(true);
+    }
+    let hashes = text.len() - text.trim_start_matches('#').len();
+    let heading = (1..=6).contains(&hashes) &&
+                  text[hashes..].chars().next().is_none_or(is_space);
+    let rule = "-*_".contains(c) && text.matches(c).count() >= 3 &&
+               text.chars().all(|d| d == c || is_space(d));
+    if heading || rule || c == '|' { Some(false) } else { None }
+}
+
+// Tells whether `line` opens an HTML block: a tag, or a comment.
+fn opens_html(line: &str) -> bool {
+    let line = line.trim_start_matches(' ');
+    let tag = match line.strip_prefix('<') {
+        Some(tag) if tag.starts_with('!') => return true,
+        Some(tag) => tag.strip_prefix('/').unwrap_or(tag),
+        None => return false,
+    };
+    let name = tag.find(|c: char| !c.is_ascii_alphanumeric() && c != '-').unwrap_or(tag.len());
+    name > 0 && tag.starts_with(|c: char| c.is_ascii_alphabetic()) &&
+    tag[name..].chars().next().is_none_or(|c| c == '>' || c == '/' || is_space(c))
+}
+
+fn is_term(word: &str) -> bool {
+    word.len() >= 2 && word.ends_with(':') && word.contains(char::is_alphanumeric)
+}
+
+// Tells whether a line starting with `word` would be read otherwise than
+// as the continuation of a paragraph, given the options: as a quotation, a
+// list item, a code fence, a heading, a term (no wider than
+// `max_term_width`)...
+fn starts_block(word: &str, metric: Metric, options: &Options) -> bool {
+    let digits = word.len() - word.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let marker = if digits > 0 {
+        matches!(&word[digits..], "." | ")")
+    } else {
+        matches!(word, "-" | "*" | "+")
+    };
+    let hashes = word.len() - word.trim_start_matches('#').len();
+    options.quotes && word.starts_with('>') ||
+    options.markdown && (marker || opening_fence(word).is_some()) ||
+    options.prose_wrap && (word.starts_with('|') || hashes == word.len() && hashes <= 6) ||
+    options.rst && (word == ".." || word == "|") ||
+    options.definitions && is_term(word) &&
+    options.max_term_width.is_none_or(|w| metric.width(word) <= w)
+}
+
+pub fn is_blank(line: &str) -> bool {
+    line.trim_matches(is_space).is_empty()
+}
+
+// Tells whether `line` has no words once stripped of the prefix and of the
+// quotation markers, if the options say so: such a line separates the
+// paragraphs as a blank one.
+pub fn is_wordless(line: &str, options: &Options) -> bool {
+    let line = match options.prefix {
+        Some(ref p) => strip_prefix(line, p).1,
+        None => line,
+    };
+    let line = if options.quotes { strip_quotes(line).1 } else { line };
+    is_blank(line)
+}
+
+// Returns the settings of a directive line such as
+// `<!-- subpar: width=60 -->`.
+fn directive(line: &str) -> Option<&str> {
+    let line = line.trim_matches(is_space);
+    let settings = line.strip_prefix("<!--")?.strip_suffix("-->")?.trim_matches(is_space);
+    settings.strip_prefix("subpar:")
+}
+
+// Applies the `settings` of a directive to `width`, the current width if
+// set by an earlier one.  The unknown settings are skipped with a warning.
+fn apply(settings: &str, width: Option<usize>, options: &Options) -> Option<usize> {
+    let mut width = width;
+    for setting in settings.split(|c| c == ',' || is_space(c)).filter(|s| !s.is_empty()) {
+        match setting.split_once('=') {
+            Some(("width", "default")) => width = Some(options.width),
+            Some(("width", value)) => match value.parse() {
+                Ok(w) if w > 0 => width = Some(w),
+                _ => eprintln!("subpar: Invalid width in directive: '{}'.", value),
+            },
+            _ => eprintln!("subpar: Unknown directive: '{}'.", setting),
+        }
+    }
+    width
+}
+
+// Puts `input` in the normalization form of the options, if any, so that
+// its forms are consistent before it is tokenized and measured.
+pub fn normalize<'a>(input: &'a str, options: &Options) -> Cow<'a, str> {
+    match options.normalization {
+        Some(Normalization::Nfc) if !is_nfc(input) => Cow::Owned(input.nfc().collect()),
+        Some(Normalization::Nfd) if !is_nfd(input) => Cow::Owned(input.nfd().collect()),
+        _ => Cow::Borrowed(input),
+    }
+}
+
+// Splits `input` into the blocks of its paragraphs (as `tokenize_markdown`
+// or `tokenize_rst` if the options say so) and the lines of its
+// directives, which are kept as they are and set the width of the
+// paragraphs following them (as are, with `only_long`, the lines which
+// fit).
+pub fn tokenize<'a>(input: &'a str, options: &Options) -> Vec<Block<'a>> {
+    tokenize_at(input, options, None)
+}
+
+// Same as `tokenize`, for a part of the input where an earlier directive
+// has set the width of the paragraphs to `width`.
+pub fn tokenize_at<'a>(input: &'a str, options: &Options, width: Option<usize>) -> Vec<Block<'a>> {
+    let metric = options.metric();
+    let mut blocks = Vec::new();
+    let mut width = width;
+    // Start of the text since the last directive, and number of blank
+    // lines before it.
+    let mut start = 0;
+    let mut blank_lines = 0;
+    let mut fence: Option<&str> = None;
+    let mut offset = 0;
+    for raw in input.split_inclusive('\n') {
+        let line = raw.strip_suffix('\n').unwrap_or(raw);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let next = offset + raw.len();
+        let mut code = fence.is_some();
+        if options.markdown {
+            match fence {
+                Some(f) if closes(line, f) => fence = None,
+                Some(_) => {}
+                None => fence = opening_fence(line),
+            }
+        }
+        code = code || fence.is_some();
+        let settings = directive(line).filter(|_| fence.is_none());
+        // With `only_long`, the lines which fit (and the code) are kept as
+        // they are, each of the others being wrapped on its own.
+        let kept = options.only_long && !is_blank(line) &&
+                   (code || metric.width(line) <= width.unwrap_or(options.width));
+        if settings.is_some() || kept {
+            let text = &input[start..offset];
+            let trailing = text.lines().rev().take_while(|l| is_blank(l)).count();
+            if text.lines().all(is_blank) {
+                blank_lines += trailing;
+            } else {
+                push_section(&mut blocks, text, blank_lines, width, options);
+                blank_lines = trailing;
+            }
+            if let Some(settings) = settings {
+                width = apply(settings, width, options);
+            }
+            let mut block = Block::verbatim(blank_lines, line);
+            block.width = width;
+            blocks.push(block);
+            start = next;
+            blank_lines = 0;
+        }
+        offset = next;
+    }
+    if start == 0 {
+        let mut blocks = tokenize_markdown(input, options);
+        for block in &mut blocks {
+            block.width = width;
+        }
+        return blocks;
+    }
+    if !input[start..].lines().all(is_blank) {
+        push_section(&mut blocks, &input[start..], blank_lines, width, options);
+    }
+    blocks
+}
+
+// Appends the blocks of `text`, which follows `blank_lines` blank lines
+// (besides its own), to `blocks`, with the lines `width` long if set.
+fn push_section<'a>(blocks: &mut Vec<Block<'a>>,
+                    text: &'a str,
+                    blank_lines: usize,
+                    width: Option<usize>,
+                    options: &Options) {
+    let leading = text.lines().take_while(|l| is_blank(l)).count();
+    let mut section = tokenize_markdown(text, options);
+    if let Some(block) = section.first_mut() {
+        block.blank_lines = blank_lines + leading;
+    }
+    for block in &mut section {
+        block.width = width;
+    }
+    blocks.extend(section);
+}
+
+// Same as `tokenize_text`, the code blocks of Markdown (fenced, or indented
+// by four spaces) being kept as they are.
+fn tokenize_markdown<'a>(input: &'a str, options: &Options) -> Vec<Block<'a>> {
+    if options.rst {
+        return tokenize_rst(input, options);
+    }
+    if !options.markdown {
+        return tokenize_text(input, options);
+    }
+    let mut blocks = Vec::new();
+    // Start of the text since the last code block, and number of blank
+    // lines before it.
+    let mut start = 0;
+    let mut blank_lines = 0;
+    // Current code block, with its fence unless it is indented.
+    let mut code: Option<(Block, Option<&str>)> = None;
+    let mut in_paragraph = false;
+    // Start of the current paragraph (or list item), and whether the
+    // current lines are those of an HTML block.
+    let mut previous = 0;
+    let mut html = false;
+    let mut offset = 0;
+    for raw in input.split_inclusive('\n') {
+        let line = raw.strip_suffix('\n').unwrap_or(raw);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let next = offset + raw.len();
+        if let Some((mut block, fence)) = code.take() {
+            match fence {
+                Some(fence) => {
+                    block.verbatim.push(line);
+                    if closes(line, fence) {
+                        blocks.push(block);
+                        start = next;
+                        blank_lines = 0;
+                        in_paragraph = false;
+                    } else {
+                        code = Some((block, Some(fence)));
+                    }
+                    offset = next;
+                    continue;
+                }
+                None if line.starts_with("    ") || line.starts_with('\t') || is_blank(line) => {
+                    block.verbatim.push(line);
+                    code = Some((block, None));
+                    offset = next;
+                    continue;
+                }
+                None => {
+                    blank_lines = split_blank_lines(&mut block);
+                    blocks.push(block);
+                    start = offset;
+                    in_paragraph = false;
+                }
+            }
+        }
+        let fence = opening_fence(line);
+        let indented = !in_paragraph && !is_blank(line) &&
+                       (line.starts_with("    ") || line.starts_with('\t'));
+        html = options.prose_wrap && !is_blank(line) &&
+               (html || !in_paragraph && opens_html(line));
+        // A setext underline makes a heading of the line before it.
+        let (kept, end) = match line_block(line, in_paragraph) {
+            Some(setext) if options.prose_wrap && fence.is_none() => {
+                (true, if setext { previous } else { offset })
+            }
+            _ if html && fence.is_none() => (true, offset),
+            _ => (false, offset),
+        };
+        if kept {
+            let text = &input[start..end];
+            let trailing = text.lines().rev().take_while(|l| is_blank(l)).count();
+            if text.lines().all(is_blank) {
+                blank_lines += trailing;
+            } else {
+                push_text(&mut blocks, text, blank_lines, options);
+                blank_lines = trailing;
+            }
+            let mut lines = input[end..next].lines();
+            let mut block = Block::verbatim(blank_lines, lines.next().unwrap_or(line));
+            block.verbatim.extend(lines);
+            blocks.push(block);
+            start = next;
+            blank_lines = 0;
+            in_paragraph = false;
+        } else if fence.is_some() || indented {
+            let text = &input[start..offset];
+            let trailing = text.lines().rev().take_while(|l| is_blank(l)).count();
+            if text.lines().all(is_blank) {
+                blank_lines += trailing;
+            } else {
+                push_text(&mut blocks, text, blank_lines, options);
+                blank_lines = trailing;
+            }
+            code = Some((Block::verbatim(blank_lines, line), fence));
+        } else {
+            if !in_paragraph || list_marker(line.trim_start_matches(is_space)).is_some() {
+                previous = offset;
+            }
+            in_paragraph = !is_blank(line);
+        }
+        offset = next;
+    }
+    match code {
+        Some((mut block, fence)) => {
+            if fence.is_none() {
+                split_blank_lines(&mut block);
+            }
+            blocks.push(block);
+        }
+        None => push_text(&mut blocks, &input[start..], blank_lines, options),
+    }
+    blocks
+}
+
+// Removes the blank lines ending `block`, which separate it from what
+// follows rather than belong to it, and returns their number.
+fn split_blank_lines(block: &mut Block) -> usize {
+    let mut blank_lines = 0;
+    while block.verbatim.last().is_some_and(|l| is_blank(l)) {
+        block.verbatim.pop();
+        blank_lines += 1;
+    }
+    blank_lines
+}
+
+// Splits `input` into its paragraphs, keeping as they are the literal
+// blocks of reStructuredText (indented after a paragraph ending with
+// `::`), its line blocks (lines starting with `|`) and its explicit markup
+// (directives and comments, starting with `..`, and their indented lines).
+fn tokenize_rst<'a>(input: &'a str, options: &Options) -> Vec<Block<'a>> {
+    let metric = options.metric();
+    let mut blocks = Vec::new();
+    // Start of the text since the last verbatim block, and number of blank
+    // lines before it.
+    let mut start = 0;
+    let mut blank_lines = 0;
+    // Current verbatim block, with the indent past which its lines go on.
+    let mut kept: Option<(Block, usize)> = None;
+    // Indent of the last line if it ends with `::`, and whether a blank
+    // line followed it.
+    let mut literal: Option<(usize, bool)> = None;
+    let mut offset = 0;
+    for raw in input.split_inclusive('\n') {
+        let line = raw.strip_suffix('\n').unwrap_or(raw);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let next = offset + raw.len();
+        let text = line.trim_start_matches(is_space);
+        let indent = metric.width(&line[..line.len() - text.len()]);
+        if let Some((mut block, base)) = kept.take() {
+            // A blank line ends a line block, whose lines go on at its indent.
+            let line_block = is_line_block(block.verbatim[0].trim_start_matches(is_space));
+            let goes_on = if is_blank(line) {
+                !line_block
+            } else {
+                indent > base || line_block && indent == base && is_line_block(text)
+            };
+            if goes_on {
+                block.verbatim.push(line);
+                kept = Some((block, base));
+                offset = next;
+                continue;
+            }
+            blank_lines = split_blank_lines(&mut block);
+            blocks.push(block);
+            start = offset;
+        }
+        if is_blank(line) {
+            if let Some((_, ref mut blank)) = literal {
+                *blank = true;
+            }
+            offset = next;
+            continue;
+        }
+        let base = match literal {
+            Some((base, true)) if indent > base => Some(base),
+            _ => None,
+        };
+        let explicit = text.strip_prefix("..")
+            .is_some_and(|t| t.is_empty() || t.starts_with(is_space));
+        if base.is_some() || explicit || is_line_block(text) {
+            let text = &input[start..offset];
+            let trailing = text.lines().rev().take_while(|l| is_blank(l)).count();
+            if text.lines().all(is_blank) {
+                blank_lines += trailing;
+            } else {
+                push_text(&mut blocks, text, blank_lines, options);
+                blank_lines = trailing;
+            }
+            kept = Some((Block::verbatim(blank_lines, line), base.unwrap_or(indent)));
+            literal = None;
+        } else {
+            literal = if text.trim_end_matches(is_space).ends_with("::") {
+                Some((indent, false))
+            } else {
+                None
+            };
+        }
+        offset = next;
+    }
+    match kept {
+        Some((mut block, _)) => {
+            split_blank_lines(&mut block);
+            blocks.push(block);
+        }
+        None => push_text(&mut blocks, &input[start..], blank_lines, options),
+    }
+    blocks
+}
+
+fn is_line_block(text: &str) -> bool {
+    text == "|" || text.strip_prefix('|').is_some_and(|t| t.starts_with(is_space))
+}
+
+// Appends the paragraphs of `text`, which follows `blank_lines` blank
+// lines (besides its own), to `blocks`.
+fn push_text<'a>(blocks: &mut Vec<Block<'a>>,
+                 text: &'a str,
+                 blank_lines: usize,
+                 options: &Options) {
+    if text.lines().all(is_blank) {
+        return;
+    }
+    let leading = text.lines().take_while(|l| is_blank(l)).count();
+    let mut paragraphs = tokenize_text(text, options);
+    if let Some(paragraph) = paragraphs.first_mut() {
+        paragraph.blank_lines = blank_lines + leading;
+    }
+    blocks.extend(paragraphs);
+}
+
+// Returns a vector of paragraphs, empty if `input` has no words.
+fn tokenize_text<'a>(input: &'a str, options: &Options) -> Vec<Block<'a>> {
+    let metric = options.metric();
+    let dictionary = options.hyphenate.and_then(|l| Standard::from_embedded(l).ok());
+    let mut text = Vec::new();
+    let mut paragraph = Vec::new();
+    let mut prefix: Option<String> = None;
+    let mut quote = 0;
+    let mut indent: Option<&str> = None;
+    // Marker of the current block if it is a list item.
+    let mut item: Option<&str> = None;
+    let mut blank_lines = 0;
+    let mut last_word: Option<Cow<str>> = None;
+    // Whether `last_word` is followed by several spaces: this only tells
+    // apart the sentence endings from the abbreviations, all the gaps
+    // between the other words being a single space.
+    let mut many_spaces = false;
+    let mut newlines = 0;
+    // Marker of the hard line break following `last_word`, if any: two
+    // trailing spaces (kept in the output) or a backslash.
+    let mut hard_break: Option<&str> = None;
+    // Whitespace ending the line of `last_word`, kept at the end of its
+    // paragraph if asked to.
+    let mut trailing = "";
+    for line in input.lines() {
+        let (line_prefix, line) = match options.prefix {
+            Some(ref p) => strip_prefix(line, p),
+            None => (None, line),
+        };
+        let (line_quote, line) = if options.quotes {
+            strip_quotes(line)
+        } else {
+            (0, line)
+        };
+        let leading = &line[..line.len() - line.trim_start_matches(is_space).len()];
+        let line_item = if options.markdown {
+            list_marker(&line[leading.len()..])
+        } else {
+            None
+        };
+        let line_item = match line_item {
+            None if options.definitions => {
+                let fits = |t: &&str| {
+                    options.max_term_width.is_none_or(|w| metric.width(t.trim_end()) <= w)
+                };
+                definition_term(&line[leading.len()..]).filter(fits)
+            }
+            _ => line_item,
+        };
+        let line = line_item.map_or(line, |m| &line[leading.len() + m.len()..]);
+        let mut first = true;
+        // Whitespace separates words; runs of it yield empty words.
+        for word in line.split(is_space) {
+            if word.is_empty() {
+                many_spaces = last_word.is_some();
+            } else {
+                // Same line, or the next one of the same paragraph.
+                let continued = newlines == 0 ||
+                                (newlines == 1 && line_quote == quote && line_item.is_none() &&
+                                 hard_break.is_none());
+                let joined = match last_word {
+                    Some(ref w) if options.dehyphenate && first && continued => {
+                        dehyphenate(w, word, dictionary.as_ref())
+                    }
+                    _ => None,
+                };
+                let joined = match last_word {
+                    Some(ref w) if options.french_spacing && joined.is_none() && continued => {
+                        french_join(w, word)
+                    }
+                    _ => joined,
+                };
+                if joined.is_some() {
+                    newlines = 0;
+                } else if let Some(last_word) = last_word.take() {
+                    // A blank line, a change of quotation depth, or a list
+                    // item or a term, ends the paragraph (every line does in
+                    // verse).
+                    let ends_paragraph = newlines > 1 ||
+                                         (first && (options.verse || line_quote != quote ||
+                                                    line_item.is_some()));
+                    let kept = if ends_paragraph && options.keep_trailing { trailing } else { "" };
+                    match hard_break.take() {
+                        Some(marker) if !ends_paragraph => {
+                            paragraph.push(Word::LineEnd(last_word + marker));
+                        }
+                        _ if options.sentences && ends_sentence(&last_word, options) => {
+                            let capitalized = options.single_space_sentences &&
+                                              word.trim_start_matches(|c| "\"'«([{".contains(c))
+                                                  .starts_with(char::is_uppercase);
+                            if many_spaces || newlines > 0 || capitalized {
+                                paragraph.push(Word::EndOfSentence(last_word + kept));
+                            } else {
+                                paragraph.push(Word::Ambiguous(last_word));
+                            }
+                        }
+                        _ => paragraph.push(Word::Normal(last_word + kept)),
+                    }
+                    many_spaces = false;
+                    if ends_paragraph {
+                        text.push(Block::new(prefix.take(), quote, indent.take(),
+                                             blank_lines, paragraph)
+                            .item(item.take()));
+                        paragraph = Vec::new();
+                        blank_lines = newlines - 1;
+                    }
+                    newlines = 0;
+                } else {
+                    // The lines before the first word start no paragraph.
+                    newlines = 0;
+                }
+                if first {
+                    // Lines lacking the prefix still belong to the block,
+                    // which is given the prefix of its first commented line.
+                    if prefix.is_none() {
+                        prefix = line_prefix.clone();
+                    }
+                    quote = line_quote;
+                    // The lines of a list item (or of a definition) are
+                    // aligned after its marker.
+                    if line_item.is_some() {
+                        item = line_item;
+                        indent = Some(leading);
+                    } else if item.is_none() {
+                        // Neither the shift of the aligned lines nor the
+                        // indent of the first line, which the output adds,
+                        // count.
+                        let leading = match options.align {
+                            Align::Center | Align::Right => "",
+                            _ if indent.is_none() => {
+                                let spaces = leading.len() - leading.trim_end_matches(' ').len();
+                                &leading[..leading.len() - spaces.min(options.first_indent)]
+                            }
+                            _ => leading,
+                        };
+                        indent = Some(indent.map_or(leading, |i| {
+                            // Tabs and spaces may be mixed: the narrowest
+                            // indent is kept.
+                            if metric.width(leading) < metric.width(i) { leading } else { i }
+                        }));
+                    }
+                    first = false;
+                }
+                let word = joined.map_or(Cow::Borrowed(word), Cow::Owned);
+                last_word = Some(match word {
+                    Cow::Borrowed(w) if options.french_spacing => french_spacing(w),
+                    Cow::Owned(ref w) if options.french_spacing => {
+                        Cow::Owned(french_spacing(w).into_owned())
+                    }
+                    _ => word,
+                });
+            }
+        }
+        if !first {
+            trailing = &line[line.trim_end_matches(is_space).len()..];
+            hard_break = if line.ends_with("  ") {
+                Some(if options.keep_trailing { trailing } else { "  " })
+            } else if line.ends_with('\\') || options.squeeze {
+                Some("")
+            } else {
+                None
+            };
+        }
+        newlines += 1;
+    }
+    if let Some(mut last_word) = last_word {
+        // As at the end of the other paragraphs, the last word only ends a
+        // sentence if it ends with the punctuation of one.
+        let ends = options.sentences && ends_sentence(&last_word, options);
+        if options.keep_trailing {
+            last_word += trailing;
+        }
+        if ends {
+            paragraph.push(Word::EndOfSentence(last_word));
+        } else {
+            paragraph.push(Word::Normal(last_word));
+        }
+    }
+    // An input without words has no paragraphs.
+    if !paragraph.is_empty() {
+        text.push(Block::new(prefix, quote, indent, blank_lines, paragraph).item(item));
+    }
+    for block in &mut text {
+        let mut words = mem::take(&mut block.words);
+        if options.fix_punct_spacing {
+            words = punctuation(words);
+        }
+        if options.break_dashes {
+            words = dashes(words, options.protect_urls);
+        }
+        block.words = syllables(words, dictionary.as_ref(), options.protect_urls);
+        if let Some(ref pattern) = options.keep_together {
+            block.words = keep_together(mem::take(&mut block.words), pattern, metric);
+        }
+        // No empty word may reach the line breaking, where it would make
+        // lines of nothing.
+        block.words.retain(|w| !w.text().is_empty());
+        block.words = glue(mem::take(&mut block.words), metric, options);
+    }
+    // A paragraph left without words (e.g. made of soft hyphens) counts as
+    // a blank line.
+    let mut blocks: Vec<Block> = Vec::with_capacity(text.len());
+    let mut skipped = 0;
+    for mut block in text {
+        if block.words.is_empty() {
+            skipped += block.blank_lines + 1;
+        } else {
+            block.blank_lines += skipped;
+            skipped = 0;
+            blocks.push(block);
+        }
+    }
+    blocks
+}
+
+// Lengths of the lines that can be made out of a sequence of words,
+// computed on demand from the cumulative widths of the words and of the
+// gaps following them.
+pub struct LineLengths {
+    // `words[i]` is the width of the first `i` words, and `spaces[i]`
+    // that of the gaps following them.
+    words: Vec<usize>,
+    spaces: Vec<usize>,
+    gaps: Vec<usize>,
+    // What each space of the gaps counts for, if not a column (see
+    // `with_glue`).
+    glue: Option<f64>,
+    // Width of what ends a line after each word (e.g. a hyphen).
+    ends: Vec<usize>,
+    penalties: Vec<usize>,
+}
+
+impl LineLengths {
+    pub fn new(line: &[Word], metric: Metric) -> LineLengths {
+        LineLengths::with_glue(line, metric, None)
+    }
+
+    // Same as `new`, but with each space of the gaps counting as `glue`
+    // columns if set (e.g. 0.33, about the width of a space in most
+    // proportional fonts), the gaps of each line being rounded together.
+    pub fn with_glue(line: &[Word], metric: Metric, glue: Option<f64>) -> LineLengths {
+        let mut words = Vec::with_capacity(line.len() + 1);
+        let mut spaces = Vec::with_capacity(line.len() + 1);
+        let mut gaps = Vec::with_capacity(line.len());
+        let mut ends = Vec::with_capacity(line.len());
+        let mut penalties = Vec::with_capacity(line.len());
+        let (mut length, mut space) = (0, 0);
+        words.push(length);
+        spaces.push(space);
+        for word in line {
+            let gap = metric.gap(word);
+            length += metric.width(word.text());
+            space += gap;
+            words.push(length);
+            spaces.push(space);
+            gaps.push(gap);
+            ends.push(metric.width(word.break_text()));
+            penalties.push(word.penalty());
+        }
+        LineLengths {
+            words,
+            spaces,
+            gaps,
+            glue,
+            ends,
+            penalties,
+        }
+    }
+
+    // Number of words.
+    pub fn len(&self) -> usize {
+        self.gaps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.gaps.is_empty()
+    }
+
+    // Length of a line starting with word `i` and ending with word `i+j`.
+    pub fn get(&self, i: usize, j: usize) -> usize {
+        self.span(i, j) + self.ends[i + j]
+    }
+
+    // Same as `get`, without what ends the line: the column of the gap
+    // after word `i+j`.
+    fn span(&self, i: usize, j: usize) -> usize {
+        let spaces = self.spaces[i + j] - self.spaces[i];
+        let gaps = match self.glue {
+            Some(glue) => (spaces as f64 * glue).round() as usize,
+            None => spaces,
+        };
+        self.words[i + j + 1] - self.words[i] + gaps
+    }
+
+    // Extra cost of ending a line with word `k`.
+    pub fn penalty(&self, k: usize) -> usize {
+        self.penalties[k]
+    }
+}
+
+pub fn line_lengths(line: &[Word], metric: Metric) -> LineLengths {
+    LineLengths::new(line, metric)
+}
+
+// Cost model of the lines.
+#[derive(Debug, Clone, Copy)]
+pub struct Cost {
+    // Power of the distance to the goal: 2 is gentler on the raggedness
+    // than 3, and 1 hardly favors even lines over full ones, much like the
+    // greedy algorithm.
+    pub exponent: u32,
+    // Cost of each character past the width.
+    pub overflow: usize,
+    // Length under which the last line of a paragraph (unless it is made
+    // as long as the others) starts to cost something.
+    pub slack: usize,
+    // If set, the last line rather costs as much as the others, divided by
+    // this reward if it is longer than the share `last_min_frac` of the
+    // width.
+    pub last_reward: Option<usize>,
+    pub last_min_frac: f64,
+}
+
+impl Cost {
+    // Cost of a line: proportional to the overflow past `width` if any,
+    // otherwise a power of the distance to `goal`.  The costs (and their
+    // sums in the line breaking) saturate rather than overflow.
+    pub fn badness(&self, line_length: usize, width: usize, goal: usize) -> usize {
+        if line_length > width {
+            self.overflow.saturating_mul(line_length - width)
+        } else {
+            line_length.abs_diff(goal).saturating_pow(self.exponent)
+        }
+    }
+
+    // Tells whether the badness of the lines up to `max` characters long,
+    // given `width` and `goal`, is a convex function of their length.
+    fn is_convex(&self, width: usize, goal: usize, max: usize) -> bool {
+        let mut previous = self.badness(0, width, goal) as i128;
+        let mut slope = i128::MIN;
+        for length in 1..max + 1 {
+            let bad = self.badness(length, width, goal) as i128;
+            if bad - previous < slope {
+                return false;
+            }
+            slope = bad - previous;
+            previous = bad;
+        }
+        true
+    }
+
+    // Cost of a last line: like TeX's \parfillskip, it may be short at no
+    // cost, down to `slack`.  With `last_reward`, it is only cheaper than
+    // the others between the share `last_min_frac` of the width and the
+    // width.
+    pub fn last_badness(&self, line_length: usize, width: usize, goal: usize) -> usize {
+        match self.last_reward {
+            _ if line_length > width => self.overflow.saturating_mul(line_length - width),
+            Some(reward) => {
+                let bad = self.badness(line_length, width, goal);
+                let band = self.last_min_frac * width as f64;
+                if band < line_length as f64 && line_length < width { bad / reward } else { bad }
+            }
+            None => self.slack.saturating_sub(line_length).saturating_pow(self.exponent),
+        }
+    }
+}
+
+// Width and goal of the lines of a paragraph, the first one of which may
+// differ from the others (e.g. with a hanging indent).
+#[derive(Debug, Clone, Copy)]
+pub struct Widths {
+    pub first: (usize, usize),
+    pub rest: (usize, usize),
+}
+
+impl Widths {
+    pub fn get(&self, first: bool) -> (usize, usize) {
+        if first { self.first } else { self.rest }
+    }
+}
+
+// Contains a vector of lines, each line being a slice of words.
+pub struct Paragraph<'a> {
+    paragraph: Vec<&'a [Word<'a>]>,
+    indent: String,
+    // Replaces `indent` on the first line.
+    first: String,
+    width: usize,
+    align: Align,
+    // Whether the ends of sentences end the lines, like hard breaks.
+    sentence_lines: bool,
+    // Whether the lines are padded with spaces to the width.
+    pad: bool,
+    metric: Metric,
+    // What the lines were broken for, to tell their cost.
+    widths: Widths,
+    cost: Cost,
+    last: bool,
+}
+
+impl<'a> Paragraph<'a> {
+    fn indent(&self, index: usize) -> &str {
+        if index == 0 { &self.first } else { &self.indent }
+    }
+
+    // Width available to the words of the line at `index`, once its indent
+    // is set aside.
+    fn maxwidth(&self, index: usize) -> usize {
+        self.width.saturating_sub(self.metric.width(self.indent(index))).max(1)
+    }
+
+    // Renders the line at `index`, without trailing whitespace unless it is
+    // to be padded to the width.
+    fn line(&self, index: usize) -> String {
+        let words = self.paragraph[index];
+        let maxwidth = self.maxwidth(index);
+        let mut gaps: Vec<usize> = words.iter().map(|w| self.metric.gap(w)).collect();
+        gaps.pop();
+        let end = words.last().map_or("", Word::break_text);
+        // Justified lines get the missing characters spread over their
+        // gaps (not between syllables, nor after ambiguous words which
+        // would then end sentences), the leftmost ones taking the
+        // remainder.  The lines before a hard break (or ending a sentence
+        // if each one starts a line) are left alone, like the last one.
+        let stretchable: Vec<bool> = words.iter()
+            .zip(&gaps)
+            .map(|(w, &gap)| gap > 0 && !matches!(w, Word::Ambiguous(_)))
+            .collect();
+        let n = stretchable.iter().filter(|&&s| s).count();
+        let hard = match words.last() {
+            Some(Word::LineEnd(_)) => true,
+            Some(Word::EndOfSentence(_)) => self.sentence_lines,
+            _ => false,
+        };
+        if self.align == Align::Justify && index + 1 < self.paragraph.len() && !hard && n > 0 {
+            let length = words.iter().map(|w| self.metric.width(w.text())).sum::<usize>() +
+                         gaps.iter().sum::<usize>() + self.metric.width(end);
+            if length < maxwidth {
+                let extra = maxwidth - length;
+                let stretched = gaps.iter_mut().zip(&stretchable).filter(|&(_, &s)| s);
+                for (k, (gap, _)) in stretched.enumerate() {
+                    *gap += extra / n + if k < extra % n { 1 } else { 0 };
+                }
+            }
+        }
+        let mut line = String::new();
+        for (k, word) in words.iter().enumerate() {
+            line.push_str(word.text());
+            if k < gaps.len() {
+                line.push_str(&" ".repeat(gaps[k]));
+            }
+        }
+        line.push_str(end);
+        let length = self.metric.width(&line);
+        if length < maxwidth {
+            let shift = match self.align {
+                Align::Center => (maxwidth - length) / 2,
+                Align::Right => maxwidth - length,
+                Align::Left | Align::Justify => 0,
+            };
+            line.insert_str(0, &" ".repeat(shift));
+        }
+        line.insert_str(0, self.indent(index));
+        if self.pad {
+            line.push_str(&" ".repeat(self.room(index, &line)));
+        }
+        line
+    }
+
+    // Total cost of the lines, as minimized by `minimum_badness`: the sum
+    // of their badness and of the penalties of the words ending them,
+    // whichever algorithm broke them.
+    pub fn total_badness(&self) -> usize {
+        self.paragraph
+            .iter()
+            .enumerate()
+            .map(|(index, words)| {
+                let (width, goal) = self.widths.get(index == 0);
+                let lengths = LineLengths::new(words, self.metric);
+                let length = lengths.get(0, words.len() - 1);
+                // Each segment of the paragraph ends like a paragraph.
+                let end = index + 1 == self.paragraph.len() ||
+                          match words.last() {
+                              Some(Word::LineEnd(_)) => true,
+                              Some(Word::EndOfSentence(_)) => self.sentence_lines,
+                              _ => false,
+                          };
+                if end && !self.last {
+                    self.cost.last_badness(length, width, goal)
+                } else {
+                    self.cost
+                        .badness(length, width, goal)
+                        .saturating_add(lengths.penalty(words.len() - 1))
+                }
+            })
+            .fold(0, usize::saturating_add)
+    }
+
+    // Number of columns left after `line`, the rendering of the line at
+    // `index`, up to the width.
+    fn room(&self, index: usize, line: &str) -> usize {
+        let maxwidth = self.metric.width(self.indent(index)) + self.maxwidth(index);
+        maxwidth.saturating_sub(self.metric.width(line))
+    }
+
+    // Marks the width after `line`, the rendering of the line at `index`,
+    // or by how much `line` overflows it.
+    fn ruler(&self, index: usize, line: &str) -> String {
+        let maxwidth = self.metric.width(self.indent(index)) + self.maxwidth(index);
+        let length = self.metric.width(line);
+        if maxwidth >= length {
+            format!("{}|{}", " ".repeat(self.room(index, line)), maxwidth)
+        } else {
+            format!("|{} +{}", maxwidth, length - maxwidth)
+        }
+    }
+
+    // Writes the lines to `out`, each one followed by `newline` (and by the
+    // ruler of the `Debug` output if `debug` is set, the characters past
+    // the width being red if `color` is).
+    pub fn write_to<W: io::Write>(&self,
+                                  out: &mut W,
+                                  newline: &str,
+                                  debug: bool,
+                                  color: bool)
+                                  -> io::Result<()> {
+        for index in 0..self.paragraph.len() {
+            let line = self.line(index);
+            if debug && color {
+                let maxwidth = self.metric.width(self.indent(index)) + self.maxwidth(index);
+                let end = line.char_indices()
+                    .map(|(i, c)| i + c.len_utf8())
+                    .take_while(|&i| self.metric.width(&line[..i]) <= maxwidth)
+                    .last()
+                    .unwrap_or(0);
+                let mut out = Ansi::new(&mut *out);
+                write!(out, "{}", &line[..end])?;
+                if end < line.len() {
+                    out.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
+                    write!(out, "{}", &line[end..])?;
+                    out.reset()?;
+                }
+                write!(out, "{}{}", self.ruler(index, &line), newline)?;
+            } else if debug {
+                write!(out, "{}{}{}", line, self.ruler(index, &line), newline)?;
+            } else {
+                write!(out, "{}{}", line, newline)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Checks the invariants of `paragraph`, the line breaking of `words`:
+// its lines are made of all the words in order, and each one fits within
+// the width unless it holds a single word (whose parts are glued).
+pub(crate) fn verify_paragraph(paragraph: &Paragraph, words: &[Word]) -> Result<(), String> {
+    let mut k = 0;
+    for (index, line) in paragraph.paragraph.iter().enumerate() {
+        if line.is_empty() {
+            return Err(format!("line {} is empty", index + 1));
+        }
+        if !ptr::eq(line.as_ptr(), words[k..].as_ptr()) {
+            return Err(format!("line {} does not follow the words of line {}", index + 1, index));
+        }
+        k += line.len();
+        let length = paragraph.metric.width(&paragraph.line(index)) -
+                     paragraph.metric.width(paragraph.indent(index));
+        let single = line[..line.len() - 1].iter().all(|w| paragraph.metric.gap(w) == 0);
+        if length > paragraph.maxwidth(index) && !single {
+            return Err(format!("line {} is {} characters long, past the width of {}",
+                               index + 1,
+                               length,
+                               paragraph.maxwidth(index)));
+        }
+    }
+    if k != words.len() {
+        return Err(format!("{} words of {} are missing", words.len() - k, words.len()));
+    }
+    Ok(())
+}
+
+impl<'a> fmt::Display for Paragraph<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for index in 0..self.paragraph.len() {
+            writeln!(f, "{}", self.line(index))?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Debug for Paragraph<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for index in 0..self.paragraph.len() {
+            let line = self.line(index);
+            writeln!(f, "{}{}", line, self.ruler(index, &line))?;
+        }
+        Ok(())
+    }
+}
+
+// Minimizes the sum of the badness of the lines (DP).  Returns the number
+// of words of each line.
+fn minimum_badness(lengths: &LineLengths, widths: Widths, last: bool, cost: Cost) -> Vec<usize> {
+    let n = lengths.len();
+    if n >= MONOTONE_WORDS {
+        if let Some(lines) = monotone_badness(lengths, widths, last, cost) {
+            return lines;
+        }
+    }
+    // `dp[m]` is the least badness of the last `m` words, with the number
+    // of words on the first of their lines.
+    let mut dp = Vec::with_capacity(n + 1);
+    dp.push((0, 0));
+    for i in (0..n).rev() {
+        let mut minbadness = None;
+        let (width, goal) = widths.get(i == 0);
+        for j in 1..n - i + 1 {
+            let length = lengths.get(i, j - 1);
+            let (bad, penalty) = if !last && i + j == n {
+                (cost.last_badness(length, width, goal), 0)
+            } else {
+                (cost.badness(length, width, goal), lengths.penalty(i + j - 1))
+            };
+            // Longer lines only overflow more: none can beat the best,
+            // whatever their penalties.
+            if length > width && minbadness.is_some_and(|(m, _)| bad >= m) {
+                break;
+            }
+            let localbad = bad.saturating_add(penalty).saturating_add(dp[n - j - i].0);
+            match minbadness {
+                None => minbadness = Some((localbad, j)),
+                Some((m, _)) if localbad < m => minbadness = Some((localbad, j)),
+                _ => {}
+            }
+        }
+        dp.push(minbadness.unwrap());
+    }
+
+    let mut lines = Vec::new();
+    let mut m = n;
+    while m > 0 {
+        lines.push(dp[m].1);
+        m -= dp[m].1;
+    }
+    lines
+}
+
+// Number of words from which `minimum_badness` tries `monotone_badness`.
+const MONOTONE_WORDS: usize = 1000;
+
+// Same as `minimum_badness`, in O(n log n) rather than O(n²) for lines of
+// many words, if the badness of the lines is a convex function of their
+// length (and the lengths grow with the words).  Then the cost of the
+// lines has the Monge property: the later a line starts, the later the
+// best start of the next one, which a queue of candidates keeps track of
+// (Galil and Park).  Returns `None` otherwise.
+fn monotone_badness(lengths: &LineLengths,
+                    widths: Widths,
+                    last: bool,
+                    cost: Cost)
+                    -> Option<Vec<usize>> {
+    let n = lengths.len();
+    let (width, goal) = widths.rest;
+    if !cost.is_convex(width, goal, lengths.get(0, n - 1)) ||
+       (1..n).any(|j| lengths.get(0, j) < lengths.get(0, j - 1)) {
+        return None;
+    }
+    // The words are counted from the end: `dp[p]` is the least badness of
+    // the last `p` words, with the number of words left after their first
+    // line.  The last line and the first one have costs of their own, and
+    // are tried apart.
+    let line = |q: usize, p: usize| {
+        let (i, j) = (n - p, n - q);
+        let (width, goal) = widths.get(i == 0);
+        let length = lengths.get(i, j - i - 1);
+        if !last && j == n {
+            cost.last_badness(length, width, goal)
+        } else {
+            cost.badness(length, width, goal).saturating_add(lengths.penalty(j - 1))
+        }
+    };
+    let mut dp: Vec<(usize, usize)> = Vec::with_capacity(n + 1);
+    dp.push((0, 0));
+    // Candidates `q` for the words left, each one being the best of them
+    // from `p` on, until the start of the next one.
+    let mut queue: VecDeque<(usize, usize)> = VecDeque::new();
+    for p in 1..n {
+        let q = p - 1;
+        if q > 0 {
+            let total = |q: usize, p: usize| dp[q].0.saturating_add(line(q, p));
+            // The newer candidate wins the ties, as the shorter line does in
+            // `minimum_badness`.
+            while let Some(&(older, from)) = queue.back() {
+                if total(q, from.max(p)) > total(older, from.max(p)) {
+                    break;
+                }
+                queue.pop_back();
+            }
+            match queue.back() {
+                None => queue.push_back((q, p)),
+                Some(&(older, from)) => {
+                    let (mut low, mut high) = (from.max(p) + 1, n);
+                    while low < high {
+                        let middle = (low + high) / 2;
+                        if total(q, middle) <= total(older, middle) {
+                            high = middle;
+                        } else {
+                            low = middle + 1;
+                        }
+                    }
+                    if low < n {
+                        queue.push_back((q, low));
+                    }
+                }
+            }
+        }
+        while queue.len() > 1 && queue[1].1 <= p {
+            queue.pop_front();
+        }
+        let mut best = (line(0, p), 0);
+        if let Some(&(q, _)) = queue.front() {
+            let total = dp[q].0.saturating_add(line(q, p));
+            if total <= best.0 {
+                best = (total, q);
+            }
+        }
+        dp.push(best);
+    }
+    let first = (0..n).map(|q| (dp[q].0.saturating_add(line(q, n)), q));
+    dp.push(first.min_by_key(|&(total, q)| (total, ::std::cmp::Reverse(q))).unwrap());
+
+    let mut lines = Vec::new();
+    let mut p = n;
+    while p > 0 {
+        lines.push(p - dp[p].1);
+        p = dp[p].1;
+    }
+    Some(lines)
+}
+
+// Cost of each gap of a justified line meeting one of the line before.
+const RIVER_PENALTY: usize = 100;
+
+// Spans of the columns of the gaps between the words `start..end` of
+// `text`, from the start of their line, once justified to `width` (as by
+// `Paragraph::line`) if `justify`.
+fn gap_spans(text: &[Word],
+             lengths: &LineLengths,
+             start: usize,
+             end: usize,
+             width: usize,
+             justify: bool)
+             -> Vec<(usize, usize)> {
+    let stretchable = |k: usize| lengths.gaps[k] > 0 && !matches!(text[k], Word::Ambiguous(_));
+    let n = (start..end - 1).filter(|&k| stretchable(k)).count();
+    let length = lengths.get(start, end - start - 1);
+    let extra = if justify && n > 0 { width.saturating_sub(length) } else { 0 };
+    let mut spans = Vec::new();
+    let mut shift = 0;
+    let mut stretched = 0;
+    for k in start..end - 1 {
+        let mut gap = lengths.gaps[k];
+        if extra > 0 && stretchable(k) {
+            gap += extra / n + if stretched < extra % n { 1 } else { 0 };
+            stretched += 1;
+        }
+        let column = lengths.span(start, k - start) + shift;
+        if gap > 0 {
+            spans.push((column, column + gap));
+        }
+        shift += gap - lengths.gaps[k];
+    }
+    spans
+}
+
+// Counts the pairs of overlapping spans of `above` and `below`, sorted,
+// respectively shifted by `a` and `b` columns.
+fn meetings(above: &[(usize, usize)], a: isize, below: &[(usize, usize)], b: isize) -> usize {
+    let (mut i, mut j, mut count) = (0, 0, 0);
+    while i < above.len() && j < below.len() {
+        let (s, e) = (above[i].0 as isize + a, above[i].1 as isize + a);
+        let (t, f) = (below[j].0 as isize + b, below[j].1 as isize + b);
+        if s < f && t < e {
+            count += 1;
+        }
+        if e < f {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    count
+}
+
+// Same as `minimum_badness`, each gap of a justified line meeting one of
+// the line below costing RIVER_PENALTY more.  The state is then made of
+// the first two lines: `dp[i][j - 1]` is the least cost of the words from
+// `i` starting with a line of `j` words, with the number of words of the
+// next line.  Only the lines which fit are considered (or single words).
+fn without_rivers(text: &[Word],
+                  lengths: &LineLengths,
+                  widths: Widths,
+                  last: bool,
+                  cost: Cost)
+                  -> Vec<usize> {
+    let n = lengths.len();
+    // The first line may be indented differently from the others.
+    let indent = widths.rest.0 as isize - widths.first.0 as isize;
+    let offset = |i: usize| if i == 0 { indent } else { 0 };
+    let mut dp: Vec<Vec<(usize, usize)>> = vec![Vec::new(); n + 1];
+    let mut spans: Vec<Vec<Vec<(usize, usize)>>> = vec![Vec::new(); n + 1];
+    for i in (0..n).rev() {
+        let (width, goal) = widths.get(i == 0);
+        for j in 1..n - i + 1 {
+            let length = lengths.get(i, j - 1);
+            if j > 1 && length > width {
+                break;
+            }
+            spans[i].push(gap_spans(text, lengths, i, i + j, width, i + j < n));
+            if i + j == n {
+                let bad = if last {
+                    cost.badness(length, width, goal)
+                } else {
+                    cost.last_badness(length, width, goal)
+                };
+                dp[i].push((bad, 0));
+                continue;
+            }
+            let bad = cost.badness(length, width, goal).saturating_add(lengths.penalty(i + j - 1));
+            let above = &spans[i][j - 1];
+            let mut best = (usize::MAX, 0);
+            for (k, &(rest, _)) in dp[i + j].iter().enumerate() {
+                let rivers = meetings(above, offset(i), &spans[i + j][k], offset(i + j));
+                let total = bad.saturating_add(rest).saturating_add(rivers * RIVER_PENALTY);
+                if total < best.0 || best.1 == 0 {
+                    best = (total, k + 1);
+                }
+            }
+            dp[i].push(best);
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut i = 0;
+    let mut j = dp[0].iter().enumerate().min_by_key(|&(_, &(c, _))| c).map_or(0, |(j, _)| j + 1);
+    while i < n {
+        lines.push(j);
+        let next = dp[i][j - 1].1;
+        i += j;
+        j = next;
+    }
+    lines
+}
+
+const LINE_PENALTY: f64 = 10.0;
+const ADJACENT_PENALTY: f64 = 3000.0;
+
+// Badness and fitness class (0: tight, 1: decent, 2: loose, 3: very loose)
+// of a line in Knuth and Plass' model, where its `gaps` stretch or shrink
+// to bring it to `goal`.
+fn stretch(line_length: usize,
+           gaps: usize,
+           width: usize,
+           goal: usize,
+           cost: Cost)
+           -> (f64, usize) {
+    if line_length > width {
+        return (cost.overflow as f64 * (line_length - width) as f64, 0);
+    }
+    let ratio = (goal as f64 - line_length as f64) / gaps.max(1) as f64;
+    let class = if ratio < -0.5 {
+        0
+    } else if ratio <= 0.5 {
+        1
+    } else if ratio <= 1.0 {
+        2
+    } else {
+        3
+    };
+    (100.0 * ratio.abs().powi(3), class)
+}
+
+// Minimizes the total demerits of the paragraph, as Knuth and Plass: each
+// line costs its squared badness and the squared penalty of its break,
+// plus a penalty when its fitness class is far from the previous line's.
+// Only the overflow and the cost of the last line of `cost` apply, the
+// badness itself being cubic as in TeX.
+// Returns the number of words of each line.
+fn knuth_plass(lengths: &LineLengths, widths: Widths, last: bool, cost: Cost) -> Vec<usize> {
+    let n = lengths.len();
+    // `best[j][c]` is the least demerits of the first `j` words when their
+    // last line is of class `c`, with the number of words on that line and
+    // the class of the line before.
+    let mut best = vec![[None; 4]; n + 1];
+    best[0][1] = Some((0.0, 0, 1));
+    for j in 1..n + 1 {
+        for i in (0..j).rev() {
+            let length = lengths.get(i, j - i - 1);
+            let (width, goal) = widths.get(i == 0);
+            let (bad, class, penalty) = if j == n && !last && length <= width {
+                (cost.last_badness(length, width, goal) as f64, 1, 0.0)
+            } else {
+                let (bad, class) = stretch(length, j - i - 1, width, goal, cost);
+                (bad, class, lengths.penalty(j - 1) as f64)
+            };
+            // As in TeX, the penalty of the break adds to the demerits
+            // of the line, whatever its badness.
+            let line = (LINE_PENALTY + bad).powi(2) + penalty.powi(2);
+            // Longer lines only overflow more: none can beat the best, nor
+            // tie with it.
+            if length > width &&
+               best[j][class].is_some_and(|(b, _, _)| b < line) {
+                break;
+            }
+            // On ties, the longest line then the lowest previous class win,
+            // the last ones tried.
+            for previous in (0..4).rev() {
+                if let Some((d, _, _)) = best[i][previous] {
+                    let mut demerits = d + line;
+                    if class.abs_diff(previous) > 1 {
+                        demerits += ADJACENT_PENALTY;
+                    }
+                    match best[j][class] {
+                        Some((b, _, _)) if b < demerits => {}
+                        _ => best[j][class] = Some((demerits, j - i, previous)),
+                    }
+                }
+            }
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut class = (0..4)
+        .filter(|&c| best[n][c].is_some())
+        .min_by(|&a, &b| best[n][a].unwrap().0.total_cmp(&best[n][b].unwrap().0))
+        .unwrap_or(1);
+    let mut j = n;
+    while j > 0 {
+        let (_, k, previous) = best[j][class].unwrap();
+        lines.push(k);
+        j -= k;
+        class = previous;
+    }
+    lines.reverse();
+    lines
+}
+
+// Puts as many words as fit on each line, in turn, as fmt does.  Returns
+// the number of words of each line.
+fn greedy(lengths: &LineLengths, widths: Widths) -> Vec<usize> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for k in 1..lengths.len() {
+        let (width, _) = widths.get(lines.is_empty());
+        if lengths.get(start, k - start) > width {
+            lines.push(k - start);
+            start = k;
+        }
+    }
+    if start < lengths.len() {
+        lines.push(lengths.len() - start);
+    }
+    lines
+}
+
+// Breaks `text` into at most `count` lines (or as many as its hard breaks
+// and the width require), the longest one, compared to its width, being
+// as short as possible.  Among the choices as balanced, the fewest
+// hyphens, then the fewest lines, are taken.
+fn balanced(lengths: &LineLengths, text: &[Word], widths: Widths, count: usize) -> Vec<usize> {
+    let n = lengths.len();
+    // No fewer lines than the greedy algorithm fills within the width and
+    // the hard breaks.
+    let mut fewest = 1;
+    let mut start = 0;
+    for k in 1..n {
+        let (width, _) = widths.get(fewest == 1);
+        if matches!(text[k - 1], Word::LineEnd(_)) || lengths.get(start, k - start) > width {
+            fewest += 1;
+            start = k;
+        }
+    }
+    let count = count.max(fewest);
+    // `best[r][i]` is the most balanced way of breaking the words from `i`
+    // into at most `r` lines: the greatest excess of a line over its width,
+    // the sum of the penalties, the number of lines, and the number of
+    // words on the first line.
+    let done: Option<(isize, usize, usize, usize)> = Some((isize::MIN, 0, 0, 0));
+    let mut best = vec![vec![None; n + 1]];
+    best[0][n] = done;
+    for r in 1..count + 1 {
+        let mut row = vec![None; n + 1];
+        row[n] = done;
+        for i in (0..n).rev() {
+            let width = widths.get(i == 0).0 as isize;
+            // A line may not go past a hard break.
+            let end = text[i..].iter()
+                .position(|w| matches!(w, Word::LineEnd(_)))
+                .map_or(n, |k| i + k + 1);
+            let mut choice: Option<(isize, usize, usize, usize)> = None;
+            for (j, rest) in best[r - 1].iter().enumerate().take(end + 1).skip(i + 1) {
+                if let Some((excess, penalty, lines, _)) = *rest {
+                    let line = lengths.get(i, j - i - 1) as isize - width;
+                    // A word longer than the width overflows anyway: only
+                    // the other lines count.
+                    let line = if j == i + 1 { line.min(0) } else { line };
+                    // Longer lines cannot beat the best choice.
+                    if choice.is_some_and(|(e, ..)| line > e) {
+                        break;
+                    }
+                    let penalty = if j < n {
+                        penalty.saturating_add(lengths.penalty(j - 1))
+                    } else {
+                        penalty
+                    };
+                    let candidate = (line.max(excess), penalty, lines + 1, j - i);
+                    if choice.is_none_or(|(e, p, l, _)| candidate < (e, p, l, 0)) {
+                        choice = Some(candidate);
+                    }
+                }
+            }
+            row[i] = choice;
+        }
+        best.push(row);
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut r) = (0, count);
+    while i < n {
+        let k = best[r][i].expect("there are enough lines for the hard breaks").3;
+        lines.push(k);
+        i += k;
+        r -= 1;
+    }
+    lines
+}
+
+// Moves words from the line before the last one to the last one, given
+// the number of words of each line, until the last line has at least
+// `min_length` characters or two words.  The lines are left alone if that
+// makes the last line longer than `width` or the line before end with an
+// ambiguous word, or if a hard break (or the end of a sentence, with
+// `sentence_lines`) precedes the last line.
+fn fill_last(lines: &mut [usize],
+             text: &[Word],
+             metric: Metric,
+             width: usize,
+             min_length: usize,
+             sentence_lines: bool) {
+    let n = lines.len();
+    if n < 2 {
+        return;
+    }
+    match text[text.len() - lines[n - 1] - 1] {
+        Word::LineEnd(_) => return,
+        Word::EndOfSentence(_) if sentence_lines => return,
+        _ => {}
+    }
+    let lengths = line_lengths(text, metric);
+    let filled = |count: usize| {
+        let start = text.len() - count;
+        let words = text[start..]
+            .iter()
+            .filter(|w| !matches!(w, Word::Syllable(_) | Word::Dash(_)))
+            .count();
+        lengths.get(start, count - 1) >= min_length || words >= 2
+    };
+    let (mut before, mut last) = (lines[n - 2], lines[n - 1]);
+    while !filled(last) {
+        if before == 1 || lengths.get(text.len() - last - 1, last) > width {
+            return;
+        }
+        before -= 1;
+        last += 1;
+    }
+    if matches!(text[text.len() - last - 1], Word::Ambiguous(_)) {
+        return;
+    }
+    lines[n - 2] = before;
+    lines[n - 1] = last;
+}
+
+// Line breaking of the words of a paragraph, their lengths being measured
+// once for all the widths it may then be broken to (e.g. as a slider moves).
+pub struct ParagraphPlanner<'a> {
+    words: &'a [Word<'a>],
+    // The hard line breaks (and the ends of sentences if they end the
+    // lines) split the paragraph into segments, broken separately.
+    segments: Vec<(&'a [Word<'a>], LineLengths)>,
+    // Lengths of the lines of the whole paragraph, only needed to balance
+    // them.
+    lengths: Option<LineLengths>,
+    metric: Metric,
+    options: &'a Options,
+}
+
+impl<'a> ParagraphPlanner<'a> {
+    pub fn new(words: &'a [Word<'a>], options: &'a Options) -> ParagraphPlanner<'a> {
+        let metric = options.metric();
+        let lengths = |words| LineLengths::with_glue(words, metric, options.glue_width);
+        let segments = words.split_inclusive(|w| match *w {
+                Word::LineEnd(_) => true,
+                Word::EndOfSentence(_) => options.sentence_lines,
+                _ => false,
+            })
+            .map(|segment| (segment, lengths(segment)))
+            .collect();
+        ParagraphPlanner {
+            words,
+            segments,
+            lengths: options.lines.map(|_| lengths(words)),
+            metric,
+            options,
+        }
+    }
+
+    // Returns the indices of the words starting a line, but the first one,
+    // when the words are broken into lines of at most `width` characters
+    // (the goal keeping its distance to the width of the options).
+    pub fn wrap(&self, width: usize, last: bool) -> Vec<usize> {
+        let goal = (self.options.goal + width).saturating_sub(self.options.width);
+        let width = (width.max(1), goal.max(1));
+        let lines = self.lines(Widths { first: width, rest: width }, last);
+        let mut points: Vec<usize> = lines.iter()
+            .scan(0, |start, &k| {
+                *start += k;
+                Some(*start)
+            })
+            .collect();
+        points.pop();
+        points
+    }
+
+    // Returns the number of words of each line.
+    fn lines(&self, mut widths: Widths, last: bool) -> Vec<usize> {
+        let options = self.options;
+        if options.squeeze {
+            // Every line of the input ends with a hard break.
+            return self.segments.iter().map(|s| s.0.len()).collect();
+        }
+        let cost = options.cost();
+        let mut lines = Vec::new();
+        match (options.lines, &self.lengths) {
+            (Some(count), Some(lengths)) => {
+                lines = balanced(lengths, self.words, widths, count);
+            }
+            _ => {
+                for (segment, lengths) in &self.segments {
+                    lines.extend(match options.algorithm {
+                        Algorithm::Badness if options.avoid_rivers &&
+                                              options.align == Align::Justify => {
+                            without_rivers(segment, lengths, widths, last, cost)
+                        }
+                        Algorithm::Badness => minimum_badness(lengths, widths, last, cost),
+                        Algorithm::KnuthPlass => knuth_plass(lengths, widths, last, cost),
+                        Algorithm::Greedy => greedy(lengths, widths),
+                    });
+                    widths.first = widths.rest;
+                }
+            }
+        }
+
+        // No length is enough to keep a widow.
+        let min_last = if options.widows { options.min_last } else { usize::MAX };
+        if min_last > 0 {
+            fill_last(&mut lines,
+                      self.words,
+                      self.metric,
+                      widths.rest.0,
+                      min_last,
+                      options.sentence_lines);
+        }
+        lines
+    }
+}
+
+// Returns the number of words of each line of `text`.
+fn break_lines(text: &[Word], widths: Widths, options: &Options) -> Vec<usize> {
+    ParagraphPlanner::new(text, options).lines(widths, options.last)
+}
+
+// Returns the indices of the words starting a line, but the first one,
+// when `words` are broken into lines of at most `width` characters as by
+// `reformat` (without indent).
+pub fn break_points(words: &[Word], width: usize, last: bool) -> Vec<usize> {
+    ParagraphPlanner::new(words, &Options::new(width, last)).wrap(width, last)
+}
+
+pub fn reformat<'a>(block: &'a Block<'a>, options: &Options) -> Paragraph<'a> {
+    let text = &block.words[..];
+    let metric = options.metric();
+    let first = block.first.clone().unwrap_or_else(|| block.indent.clone()) +
+                &" ".repeat(options.first_indent);
+    let indent = block.indent.clone() + &" ".repeat(options.hang);
+    let (first, indent) = match options.indent_tabs {
+        Some(tabs) => (metric.reindent(&first, tabs), metric.reindent(&indent, tabs)),
+        None => (first, indent),
+    };
+    // A directive may change the width, the goal keeping its distance.
+    let width = block.width.unwrap_or(options.width);
+    let goal = (options.goal + width).saturating_sub(options.width);
+    // The indent counts against the width, but at least one column is
+    // always left to the words.
+    let measure = |indent: &str| {
+        let indent = metric.width(indent);
+        (width.saturating_sub(indent).max(1), goal.saturating_sub(indent).max(1))
+    };
+    let widths = Widths {
+        first: measure(&first),
+        rest: measure(&indent),
+    };
+
+    // Split the words according to the sizes of the lines.
+    let lines = break_lines(text, widths, options);
+    let mut paragraph = Vec::with_capacity(lines.len());
+    let mut i = 0;
+    for k in lines {
+        paragraph.push(&text[i..i + k]);
+        i += k;
+    }
+    Paragraph {
+        paragraph,
+        indent,
+        first,
+        width,
+        align: options.align,
+        sentence_lines: options.sentence_lines,
+        pad: options.pad,
+        metric,
+        widths,
+        cost: options.cost(),
+        last: options.last,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Whitespace at the edges of the input, of its lines and of its
+    // paragraphs, mixed with blank lines.
+    const PATHOLOGICAL: &[&str] = &["",
+                                    " ",
+                                    "\n",
+                                    "\n\n\n",
+                                    " \t \n \r\n\u{a0}\n",
+                                    "  foo",
+                                    "foo  ",
+                                    "foo \n",
+                                    "\n\nfoo bar baz\n",
+                                    " \n foo \n\n  \n bar  baz \n\n",
+                                    "foo\n \n\t\nbar",
+                                    "foo \u{ad} bar"];
+
+    #[test]
+    fn no_empty_words() {
+        let options = Options::new(10, false);
+        for input in PATHOLOGICAL {
+            for block in tokenize(input, &options) {
+                assert!(!block.words.is_empty(), "{:?}", input);
+                assert!(block.words.iter().all(|w| !w.text().is_empty()), "{:?}", input);
+            }
+        }
+    }
+
+    #[test]
+    fn leading_blank_lines() {
+        let options = Options::new(79, false);
+        for input in &["\n\nfoo bar baz\n", " \n\t\n \nfoo bar baz", "\r\n\r\nfoo bar\r\nbaz"] {
+            let blocks = tokenize(input, &options);
+            assert_eq!(blocks.len(), 1, "{:?}", input);
+            let words: Vec<&str> = blocks[0].words.iter().map(Word::text).collect();
+            assert_eq!(words, ["foo", "bar", "baz"]);
+        }
+    }
+
+    // The words of a paragraph, some of them syllables, of varied lengths.
+    fn syllables(n: usize) -> Vec<Word<'static>> {
+        const TEXT: &str = "abcdefghijklmnopqrstuvwxyz";
+        (0..n).map(|k| {
+                  let text = Cow::Borrowed(&TEXT[..1 + k * 7 % 11]);
+                  if k % 3 == 1 { Word::Syllable(text) } else { Word::Normal(text) }
+              })
+              .collect()
+    }
+
+    // Total cost of `lines` as `minimum_badness` counts it.
+    fn layout_cost(lengths: &LineLengths,
+                   widths: Widths,
+                   last: bool,
+                   cost: Cost,
+                   lines: &[usize])
+                   -> usize {
+        let mut i = 0;
+        let mut total = 0usize;
+        for &k in lines {
+            let (width, goal) = widths.get(i == 0);
+            let length = lengths.get(i, k - 1);
+            total = total.saturating_add(if !last && i + k == lengths.len() {
+                cost.last_badness(length, width, goal)
+            } else {
+                cost.badness(length, width, goal).saturating_add(lengths.penalty(i + k - 1))
+            });
+            i += k;
+        }
+        total
+    }
+
+    #[test]
+    fn minimum_badness_is_minimal() {
+        let n = 10;
+        let words = syllables(n);
+        let lengths = LineLengths::new(&words, Options::new(79, false).metric());
+        for overflow in [0, 1, 10, 1000] {
+            for width in 5..30 {
+                for (last, last_reward) in [(false, None), (false, Some(100)), (true, None)] {
+                    let cost = Cost {
+                        overflow,
+                        slack: width / 4,
+                        last_reward,
+                        ..Options::new(width, false).cost()
+                    };
+                    let widths = Widths { first: (width, width), rest: (width, width) };
+                    let lines = minimum_badness(&lengths, widths, last, cost);
+                    assert_eq!(lines.iter().sum::<usize>(), n);
+                    // Every layout, a break after word `k` for each bit `k`.
+                    let best = (0..1 << (n - 1))
+                        .map(|breaks: usize| {
+                            let mut lines = vec![1];
+                            for k in 0..n - 1 {
+                                if breaks & 1 << k != 0 {
+                                    lines.push(1);
+                                } else {
+                                    *lines.last_mut().unwrap() += 1;
+                                }
+                            }
+                            layout_cost(&lengths, widths, last, cost, &lines)
+                        })
+                        .min()
+                        .unwrap();
+                    assert_eq!(layout_cost(&lengths, widths, last, cost, &lines),
+                               best,
+                               "overflow {} width {} last {}",
+                               overflow,
+                               width,
+                               last);
+                }
+            }
+        }
+    }
+
+    const TEXT: &str = "Lorem ipsum dolor sit amet, consectetur adipiscing elit.  Sed do \
+                        eiusmod tempor incididunt ut labore et dolore magna aliqua.  Ut \
+                        enim ad minim veniam, quis nos\u{ad}trud exer\u{ad}ci\u{ad}ta\u{ad}tion \
+                        ullamco laboris nisi ut aliquip ex ea commodo consequat—duis aute \
+                        irure dolor in reprehenderit in voluptate velit esse cillum dolore \
+                        eu fugiat nulla pariatur.  Excepteur sint occaecat cupidatat non \
+                        proident, sunt in culpa qui officia deserunt mollit anim id est \
+                        laborum.\n";
+
+    // Breaks `input` with `options` and checks every paragraph.
+    fn verify(input: &str, options: &Options) {
+        for block in tokenize(input, options) {
+            let paragraph = reformat(&block, options);
+            if let Err(e) = verify_paragraph(&paragraph, &block.words) {
+                panic!("{} at width {} with {:?}", e, options.width, options.algorithm);
+            }
+        }
+    }
+
+    // The options selecting each line breaking algorithm.
+    fn algorithms(width: usize) -> Vec<Options> {
+        let mut all = Vec::new();
+        for algorithm in [Algorithm::Badness, Algorithm::KnuthPlass, Algorithm::Greedy] {
+            let mut options = Options::new(width, false);
+            options.algorithm = algorithm;
+            all.push(options);
+        }
+        let mut options = Options::new(width, false);
+        options.align = Align::Justify;
+        options.avoid_rivers = true;
+        all.push(options);
+        let mut options = Options::new(width, false);
+        options.lines = Some(4);
+        all.push(options);
+        all
+    }
+
+    #[test]
+    fn every_algorithm_keeps_the_invariants() {
+        for width in 1..90 {
+            for mut options in algorithms(width) {
+                verify(TEXT, &options);
+                options.last = true;
+                verify(TEXT, &options);
+            }
+        }
+    }
+
+    #[test]
+    fn monotone_badness_keeps_the_invariants() {
+        // Enough words for `minimum_badness` to try `monotone_badness`.
+        let input = TEXT.trim_end().repeat(MONOTONE_WORDS / 60);
+        for width in [10, 33, 79] {
+            let options = Options::new(width, false);
+            let words = &tokenize(&input, &options)[0].words;
+            assert!(words.len() >= MONOTONE_WORDS);
+            let lengths = LineLengths::new(words, options.metric());
+            let widths = Widths { first: (width, width), rest: (width, width) };
+            assert!(monotone_badness(&lengths, widths, false, options.cost()).is_some());
+            verify(&input, &Options::new(width, false));
+            verify(&input, &Options::new(width, true));
+        }
+    }
+
+    // `knuth_plass` trying every line in order, the first best one kept.
+    fn knuth_plass_forward(lengths: &LineLengths,
+                           widths: Widths,
+                           last: bool,
+                           cost: Cost)
+                           -> Vec<usize> {
+        let n = lengths.len();
+        let mut best = vec![[None; 4]; n + 1];
+        best[0][1] = Some((0.0, 0, 1));
+        for j in 1..n + 1 {
+            for i in 0..j {
+                let length = lengths.get(i, j - i - 1);
+                let (width, goal) = widths.get(i == 0);
+                let (bad, class, penalty) = if j == n && !last && length <= width {
+                    (cost.last_badness(length, width, goal) as f64, 1, 0.0)
+                } else {
+                    let (bad, class) = stretch(length, j - i - 1, width, goal, cost);
+                    (bad, class, lengths.penalty(j - 1) as f64)
+                };
+                let line = (LINE_PENALTY + bad).powi(2) + penalty.powi(2);
+                for previous in 0..4 {
+                    if let Some((d, _, _)) = best[i][previous] {
+                        let mut demerits = d + line;
+                        if class.abs_diff(previous) > 1 {
+                            demerits += ADJACENT_PENALTY;
+                        }
+                        match best[j][class] {
+                            Some((b, _, _)) if b <= demerits => {}
+                            _ => best[j][class] = Some((demerits, j - i, previous)),
+                        }
+                    }
+                }
+            }
+        }
+        let mut lines = Vec::new();
+        let mut class = (0..4)
+            .filter(|&c| best[n][c].is_some())
+            .min_by(|&a, &b| best[n][a].unwrap().0.total_cmp(&best[n][b].unwrap().0))
+            .unwrap_or(1);
+        let mut j = n;
+        while j > 0 {
+            let (_, k, previous) = best[j][class].unwrap();
+            lines.push(k);
+            j -= k;
+            class = previous;
+        }
+        lines.reverse();
+        lines
+    }
+
+    #[test]
+    fn knuth_plass_breaks_ties_forward() {
+        let options = Options::new(79, false);
+        let blocks = tokenize(TEXT, &options);
+        for words in [&syllables(12)[..], &blocks[0].words] {
+            let lengths = LineLengths::new(words, options.metric());
+            for overflow in [0, 1, 1000] {
+                for width in 1..50 {
+                    for last in [false, true] {
+                        let cost = Cost { overflow, ..Options::new(width, false).cost() };
+                        let widths = Widths { first: (width, width), rest: (width, width) };
+                        assert_eq!(knuth_plass(&lengths, widths, last, cost),
+                                   knuth_plass_forward(&lengths, widths, last, cost),
+                                   "overflow {} width {} last {}",
+                                   overflow,
+                                   width,
+                                   last);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dehyphenate_keeps_the_compounds() {
+        let english = Standard::from_embedded(Language::EnglishUS).unwrap();
+        assert_eq!(dehyphenate("well-", "known", None).as_deref(), Some("well-known"));
+        assert_eq!(dehyphenate("hyphen-", "ation", None).as_deref(), Some("hyphen-ation"));
+        assert_eq!(dehyphenate("hyphen-", "ation,", Some(&english)).as_deref(),
+                   Some("hyphenation,"));
+        assert_eq!(dehyphenate("Jean-", "Paul", Some(&english)), None);
+        assert_eq!(dehyphenate("-", "foo", Some(&english)), None);
+        assert_eq!(dehyphenate("foo--", "bar", Some(&english)), None);
+        assert_eq!(dehyphenate("in-ci-", "didunt", Some(&english)).as_deref(),
+                   Some("incididunt"));
+        assert_eq!(dehyphenate("char-ac-", "ters!", Some(&english)).as_deref(),
+                   Some("characters!"));
+    }
+
+    #[test]
+    fn ruler_of_overflowing_lines() {
+        let word = "a".repeat(91);
+        let input = format!("{} b c", word);
+        let options = Options::new(79, false);
+        let blocks = tokenize(&input, &options);
+        let paragraph = reformat(&blocks[0], &options);
+        assert_eq!(format!("{:?}", paragraph),
+                   format!("{}|79 +12\nb c{}|79\n", word, " ".repeat(76)));
+        let mut out = Vec::new();
+        paragraph.write_to(&mut out, "\n", true, false).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), format!("{:?}", paragraph));
+    }
+
+    #[test]
+    fn last_word_of_the_input() {
+        let options = Options::new(79, false);
+        for input in &["Title", "Title\n", "a b c", "a, b, c\n\n", "(see 3.2)"] {
+            let blocks = tokenize(input, &options);
+            assert!(matches!(blocks[0].words.last(), Some(Word::Normal(_))), "{:?}", input);
+        }
+        for input in &["It ends.", "Does it?\n", "It does!  \n", "\"End.\""] {
+            let blocks = tokenize(input, &options);
+            assert!(matches!(blocks[0].words.last(), Some(Word::EndOfSentence(_))),
+                    "{:?}",
+                    input);
+        }
+    }
+
+    #[test]
+    fn runs_of_spaces_end_no_sentence() {
+        let mut options = Options::new(79, false);
+        for lines in [false, true] {
+            options.sentence_lines = lines;
+            let blocks = tokenize("foo    bar baz.", &options);
+            let words = &blocks[0].words;
+            assert!(matches!(words[..],
+                             [Word::Normal(_), Word::Normal(_), Word::EndOfSentence(_)]));
+            assert_eq!(reformat(&blocks[0], &options).to_string(), "foo bar baz.\n");
+        }
+    }
+
+    #[test]
+    fn fill_last_keeps_the_sentence_lines() {
+        let mut options = Options::new(40, false);
+        options.sentence_lines = true;
+        options.min_last = 20;
+        let input = "This is a long first sentence that goes on and on.  Short.";
+        let blocks = tokenize(input, &options);
+        assert_eq!(reformat(&blocks[0], &options).to_string(),
+                   "This is a long first sentence that goes\non and on.\nShort.\n");
+    }
+
+    #[test]
+    fn balanced_lines_keep_the_width() {
+        let mut options = Options::new(30, false);
+        options.lines = Some(2);
+        let blocks = tokenize(TEXT, &options);
+        let paragraph = reformat(&blocks[0], &options);
+        assert!(paragraph.paragraph.len() > 2);
+        assert!(paragraph.to_string().lines().all(|line| line.chars().count() <= 30));
+        options.width = 79;
+        let blocks = tokenize("one two three four five six seven eight nine ten", &options);
+        assert_eq!(reformat(&blocks[0], &options).to_string(),
+                   "one two three four five\nsix seven eight nine ten\n");
+    }
+
+    #[test]
+    fn last_line_reward() {
+        let mut options = Options::new(40, false);
+        assert_eq!(options.cost().last_badness(5, 40, 40), 0);
+        options.last_reward = Some(100);
+        let cost = options.cost();
+        // Up to a quarter of the width, as costly as the other lines.
+        assert_eq!(cost.last_badness(5, 40, 40), cost.badness(5, 40, 40));
+        assert_eq!(cost.last_badness(10, 40, 40), cost.badness(10, 40, 40));
+        assert_eq!(cost.last_badness(11, 40, 40), cost.badness(11, 40, 40) / 100);
+        assert_eq!(cost.last_badness(41, 40, 40), cost.badness(41, 40, 40));
+        options.last_min_frac = 0.5;
+        assert_eq!(options.cost().last_badness(11, 40, 40), cost.badness(11, 40, 40));
+        assert_eq!(options.cost().last_badness(21, 40, 40), cost.badness(21, 40, 40) / 100);
+    }
+
+    #[test]
+    fn glue_of_a_line_wherever_it_is() {
+        let metric = Options::new(79, false).metric();
+        let words = |text: &'static str| -> Vec<Word> {
+            text.split(' ').map(|w| Word::Normal(Cow::Borrowed(w))).collect()
+        };
+        let first = LineLengths::with_glue(&words("aa bb"), metric, Some(0.5));
+        let after = LineLengths::with_glue(&words("xx aa bb"), metric, Some(0.5));
+        assert_eq!(first.get(0, 1), after.get(1, 1));
+        let lengths = LineLengths::with_glue(&words("a b c d e f"), metric, Some(0.5));
+        for i in 0..5 {
+            assert_eq!(lengths.get(i, 1), first.get(0, 1) - 2, "{}", i);
+        }
+    }
+
+    #[test]
+    fn blank_lines_between_paragraphs() {
+        let blocks = tokenize(" \n foo \n\n  \n bar  baz \n\n", &Options::new(79, false));
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[1].blank_lines, 2);
+        assert_eq!(blocks[1].words.len(), 2);
+    }
+}
@@ -0,0 +1,20 @@
+// Bindings for JavaScript, e.g.
+//
+//     import { reformat_str } from "subpar";
+//     reformat_str(text, 72, false);
+
+use wasm_bindgen::prelude::*;
+use reflow::Options;
+
+/// Same as `subpar::reformat_str`.
+#[wasm_bindgen]
+pub fn reformat_str(input: &str, width: usize, last: bool) -> String {
+    ::reformat_str(input, width, last)
+}
+
+/// Same as `subpar::to_json`, with the default options but for `width`
+/// and `last`.
+#[wasm_bindgen]
+pub fn to_json(input: &str, width: usize, last: bool) -> String {
+    ::to_json(input, &Options::new(width, last))
+}